@@ -0,0 +1,56 @@
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use glob::Pattern;
+use serde::Deserialize;
+
+pub const CONFIG_FILE_NAME: &str = "sv_auto_order.toml";
+
+/// Project settings from a sv_auto_order.toml, the command line adds to them or overrides them
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Only used if the command line gives no sources
+    pub sources: Vec<PathBuf>,
+    pub scan: Vec<PathBuf>,
+    pub include_paths: Vec<PathBuf>,
+    pub library_dirs: Vec<PathBuf>,
+    pub defines: Vec<String>,
+    pub exclude: Vec<String>,
+    pub separator: Option<String>,
+    pub relative_to: Option<PathBuf>,
+}
+
+impl Config {
+    /// Finds the config file in the current directory or the closest parent.
+    /// The path goes up with .., so that the sources it lists stay relative
+    pub fn find() -> Option<PathBuf> {
+        let cwd = std::env::current_dir().ok()?;
+        let ups = cwd.ancestors().position(|dir| dir.join(CONFIG_FILE_NAME).is_file())?;
+        Some(std::iter::repeat_n(Path::new(".."), ups).collect::<PathBuf>().join(CONFIG_FILE_NAME))
+    }
+
+    /// Reads a config file, its relative paths are resolved from its own directory
+    pub fn load(path: &Path) -> Result<Config> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read config {}", path.display()))?;
+        let mut config: Config = toml::from_str(&text).with_context(|| format!("Invalid config {}", path.display()))?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let resolve = |p: &mut PathBuf| {
+            if p.is_relative() {
+                *p = base.join(&p);
+            }
+        };
+        config.sources.iter_mut()
+            .chain(&mut config.scan)
+            .chain(&mut config.include_paths)
+            .chain(&mut config.library_dirs)
+            .chain(&mut config.relative_to)
+            .for_each(resolve);
+        // Patterns starting with a wildcard match anywhere, like *_tb.sv
+        for pattern in &mut config.exclude {
+            if !pattern.starts_with('*') && Path::new(pattern).is_relative() && !base.as_os_str().is_empty() {
+                *pattern = format!("{}/{}", Pattern::escape(&base.to_string_lossy()), pattern);
+            }
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use serde::Serialize;
+
+/// Set by --quiet
+static QUIET: AtomicBool = AtomicBool::new(false);
+/// Set by --error-format
+static FORMAT: AtomicU8 = AtomicU8::new(Format::Text as u8);
+
+/// Prints a warning on stderr, unless --quiet is given.
+/// Takes a category, then the message, optionally after the file it is about
+macro_rules! warning {
+    (file: $file:expr, $category:literal, $($arg:tt)*) => {
+        crate::diagnostics::Diagnostic::warning($category, format!($($arg)*)).file($file).emit()
+    };
+    ($category:literal, $($arg:tt)*) => {
+        crate::diagnostics::Diagnostic::warning($category, format!($($arg)*)).emit()
+    };
+}
+
+/// How diagnostics are printed
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Error: and Warning: lines
+    Text,
+    /// One JSON object per line
+    Json,
+    /// GitHub Actions workflow commands, which show up as annotations
+    Github,
+}
+
+pub fn configure(format: Format, quiet: bool) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn format() -> Format {
+    match FORMAT.load(Ordering::Relaxed) {
+        1 => Format::Json,
+        2 => Format::Github,
+        _ => Format::Text,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A warning or error. The message is complete on its own, the location is only there for tools
+#[derive(Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+    pub category: &'static str,
+}
+
+impl Diagnostic {
+    pub fn error(category: &'static str, message: String) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, file: None, line: None, column: None, message, category }
+    }
+
+    pub fn warning(category: &'static str, message: String) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, ..Diagnostic::error(category, message) }
+    }
+
+    /// An error, located at the syntax error or in the file that failed to parse if there is one
+    pub fn from_error(category: &'static str, err: &anyhow::Error) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(category, format!("{:#}", err));
+        if let Some(syntax) = err.downcast_ref::<SyntaxError>() {
+            diagnostic.file = Some(syntax.path.to_string_lossy().into_owned());
+            diagnostic.line = Some(syntax.line);
+            diagnostic.column = Some(syntax.column);
+        } else if let Some(Parsing(path)) = err.downcast_ref::<Parsing>() {
+            diagnostic.file = Some(path.to_string_lossy().into_owned());
+        }
+        diagnostic
+    }
+
+    pub fn file(mut self, path: impl AsRef<Path>) -> Diagnostic {
+        self.file = Some(path.as_ref().to_string_lossy().into_owned());
+        self
+    }
+
+    pub fn emit(self) {
+        if self.severity == Severity::Warning && QUIET.load(Ordering::Relaxed) {
+            return
+        }
+        match format() {
+            Format::Text => match self.severity {
+                Severity::Error => eprintln!("Error: {}", self.message),
+                Severity::Warning => eprintln!("Warning: {}", self.message),
+            },
+            Format::Json => eprintln!("{}", serde_json::to_string(&self).unwrap()),
+            Format::Github => {
+                let mut properties = Vec::new();
+                if let Some(file) = &self.file {
+                    properties.push(format!("file={}", escape_property(file)));
+                }
+                if let Some(line) = self.line {
+                    properties.push(format!("line={}", line));
+                }
+                if let Some(column) = self.column {
+                    properties.push(format!("col={}", column));
+                }
+                properties.push(format!("title={}", self.category));
+                let command = match self.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                };
+                eprintln!("::{} {}::{}", command, properties.join(","), escape_data(&self.message));
+            }
+        }
+    }
+}
+
+/// Workflow commands end at a newline, so those in messages are percent-encoded
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// Context of the errors of a file that failed to parse
+#[derive(Debug)]
+pub struct Parsing(pub PathBuf);
+
+impl fmt::Display for Parsing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "While parsing {}", self.0.display())
+    }
+}
+
+/// A syntax error at a known position, with the line it is on
+#[derive(Debug)]
+pub struct SyntaxError {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub snippet: String,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Syntax error at {}:{}:{}\n    {}", self.path.display(), self.line, self.column, self.snippet)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
@@ -1,93 +1,781 @@
-use sv_parser::{parse_sv, unwrap_node, SyntaxTree, Defines, RefNode};
+use sv_parser::{parse_sv_str, unwrap_node, SyntaxTree, Defines, RefNode, NodeEvent, ModuleDeclaration, InterfaceDeclaration, ProgramDeclaration, TypeDeclarationKeyword, PsClassIdentifier, PackageScope,
+                NetPortHeaderOrInterfacePortHeader, InterfacePortHeader, TypeDeclaration, ListOfPortConnections, NamedPortConnection, ClassQualifierOrPackageScope, Locate,
+                LiblistClause, LibraryIdentifier, CellIdentifier, Symbol, ConfigRuleStatement, UseClause};
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::hash::{Hash, Hasher};
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
+use rayon::prelude::*;
+use glob::Pattern;
+use crate::diagnostics::SyntaxError;
+
+// IEEE 1800 built-in gate and switch primitives
+const BUILTIN_PRIMITIVES: &[&str] = &[
+    "and", "nand", "or", "nor", "xor", "xnor",
+    "buf", "not", "bufif0", "bufif1", "notif0", "notif1",
+    "pullup", "pulldown",
+    "nmos", "pmos", "rnmos", "rpmos", "cmos", "rcmos",
+    "tran", "rtran", "tranif0", "tranif1", "rtranif0", "rtranif1",
+];
+
+// Name of the first identifier of the given kinds under a node. When there is none,
+// the node is noted in skipped and the enclosing loop moves on to the next node
+macro_rules! ident_or_skip {
+    ($ast:expr, $skipped:expr, $node:expr, $($kind:ident),+) => {{
+        let node = RefNode::from($node);
+        match unwrap_node!(node.clone(), $($kind),+) {
+            Some(id) => get_ident_string($ast, id)?,
+            None => {
+                $skipped.push(format!("{} without identifier", describe_node($ast, node)));
+                continue;
+            }
+        }
+    }};
+}
+
+const COMPILER_DIRECTIVES: &[&str] = &[
+    "else", "endif", "include", "undefineall", "resetall", "timescale",
+    "celldefine", "endcelldefine", "default_nettype", "unconnected_drive", "nounconnected_drive",
+    "pragma", "line", "begin_keywords", "end_keywords", "protect", "endprotect", "__FILE__", "__LINE__",
+];
+
+/// Language standard, selects the reserved keywords
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Standard {
+    Sv2017,
+    Sv2012,
+    V2001,
+    V95,
+}
+
+impl Standard {
+    /// Plain Verilog files predate the SystemVerilog keywords
+    pub fn for_path(path: &Path) -> Standard {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("v") => Standard::V2001,
+            _ => Standard::Sv2017,
+        }
+    }
+
+    fn version_specifier(self) -> &'static str {
+        match self {
+            Standard::Sv2017 => "1800-2017",
+            Standard::Sv2012 => "1800-2012",
+            Standard::V2001 => "1364-2001",
+            Standard::V95 => "1364-1995",
+        }
+    }
+}
+
+/// The libraries config blocks bind cells from, when sources are mapped to libraries
+#[derive(Default)]
+pub struct ConfigRules {
+    /// Libraries searched in order for the cells without a more specific rule
+    pub default_liblist: Vec<String>,
+    /// Libraries searched for a cell, from cell ... liblist rules
+    pub cell_liblists: HashMap<String, Vec<String>>,
+    /// Cells replaced by a cell of a library, from cell ... use rules and the design statement
+    pub cell_uses: HashMap<String, (Option<String>, String)>,
+}
+
+pub struct ParseOptions {
+    pub builtin_filter: bool,
+    pub deep_resolve: bool,
+    pub compilation_unit: bool,
+    pub macro_deps: bool,
+    pub propagate_defines: bool,
+    pub all_ifdef_branches: bool,
+    pub uvm: bool,
+    pub macro_instances: bool,
+    pub hier_deps: bool,
+    pub timescale_first: bool,
+    /// Search includes in the directory of each file after the include paths
+    pub parent_incdir: bool,
+    /// Leave `include directives unexpanded, for trees where headers don't declare anything
+    pub skip_includes: bool,
+    /// Compile sources that look like headers, instead of only taking their macros
+    pub compile_headers: bool,
+    /// Fail on syntax errors instead of keeping whatever parsed before them
+    pub strict_parse: bool,
+    /// Overrides the standard picked from each file's extension
+    pub std: Option<Standard>,
+}
 
 pub struct File {
     pub name: PathBuf,
     pub modules_defined: HashSet<String>,
     pub modules_used: HashSet<String>,
+    pub modules_nested: HashSet<String>,
     pub packages_defined: HashSet<String>,
     pub packages_used: HashSet<String>,
+    pub interfaces_defined: HashSet<String>,
+    pub interfaces_used: HashSet<String>,
+    pub programs_defined: HashSet<String>,
+    pub programs_used: HashSet<String>,
+    pub checkers_defined: HashSet<String>,
+    pub checkers_used: HashSet<String>,
+    pub udps_defined: HashSet<String>,
+    pub udps_used: HashSet<String>,
+    pub configs_defined: HashSet<String>,
+    pub configs_used: HashSet<String>,
+    pub config_rules: ConfigRules,
+    pub generic_interface_ports: Vec<(String, String)>,
+    /// The interface instances and ports connected by name to the ports of instances, as (module, port, interface)
+    pub interface_connections: Vec<(String, String, String)>,
+    pub package_symbols: HashSet<String>,
+    pub unit_symbols_defined: HashSet<String>,
+    pub symbols_used: HashSet<String>,
+    pub includes: Vec<PathBuf>,
+    /// Number of `include directives left unexpanded by --skip-includes
+    pub skipped_includes: usize,
+    /// Whether the file or its headers set a `timescale
+    pub has_timescale: bool,
+    /// First components of hierarchical references that aren't local instances
+    pub hier_refs: HashSet<String>,
+    pub protected_regions: usize,
+    /// Nodes ignored because their identifier couldn't be found
+    pub skipped_nodes: Vec<String>,
+    /// Instantiated names paired with the macro whose expansion produced them
+    pub macro_instances: Vec<(String, String)>,
+    pub macros_defined: HashSet<String>,
+    pub macros_used: HashSet<String>,
     pub defines: Defines,
+    /// Uses of the cells matching --ignore-module, taken out of the used sets, with the kind of cell
+    pub ignored_uses: Vec<(String, &'static str)>,
+    /// Headers passed as sources only provide macros, they aren't part of the order
+    pub header: bool,
+    /// Library files given with -v are only compiled if another file uses them
+    pub library: bool,
+    /// The library --libmap puts the file in
+    pub work_library: Option<String>,
+    /// The parser gave up on part of the file, so its definitions may be missing some names
+    pub incomplete: bool,
     pub ast: SyntaxTree,
 }
 
 impl File {
-    pub fn new(path: &Path, incdirs: &[&Path]) -> Result<File> {
-        let defines = HashMap::new();
+    pub fn new(path: &Path, incdirs: &[&Path], pre_defines: &Defines, options: &ParseOptions) -> Result<File> {
+        let mut file = Self::parse(path, incdirs, pre_defines, options)?;
+        if options.all_ifdef_branches {
+            // Each macro the conditionals test is flipped in turn, so every branch is seen by at least one parse
+            let text = std::fs::read_to_string(path)?;
+            let variants = Self::collect_guards(&text).into_par_iter()
+                .map(|guard| {
+                    let mut defines = pre_defines.clone();
+                    if defines.remove(&guard).is_none() {
+                        defines.insert(guard.clone(), None);
+                    }
+                    (guard, Self::parse(path, incdirs, &defines, options))
+                })
+                .collect::<Vec<_>>();
+            for (guard, variant) in variants {
+                match variant {
+                    Ok(variant) => file.merge(variant),
+                    // A branch may only make sense with other macros, the others still count
+                    Err(err) => warning!(file: path, "parse", "{}: skipping the parse with `{} flipped: {:#}", path.display(), guard, err),
+                }
+            }
+        }
+        Ok(file)
+    }
+
+    fn parse(path: &Path, incdirs: &[&Path], pre_defines: &Defines, options: &ParseOptions) -> Result<File> {
         let mut incdirs = incdirs.to_vec();
-        let parent_dir = path.parent().unwrap();
-        incdirs.push(parent_dir);
-        let (ast, defines) = parse_sv(path, &defines, &incdirs, false, true)?;
+        if options.parent_incdir {
+            let parent_dir = path.parent().unwrap();
+            incdirs.push(parent_dir);
+        }
+        let mut source = std::fs::read_to_string(path)?;
+        let protected_regions = Self::strip_protected(&mut source);
+        let mut pre_defines = pre_defines.clone();
+        if options.uvm {
+            Self::stub_uvm(&mut source, &mut pre_defines);
+        }
+        let std = options.std.unwrap_or_else(|| Standard::for_path(path));
+        let mut prefix_len = 0;
+        if std != Standard::Sv2017 {
+            // Same as a `begin_keywords around the whole file, directives in the file itself still take precedence
+            let prefix = format!("`begin_keywords \"{}\"\n", std.version_specifier());
+            prefix_len = prefix.len();
+            source.insert_str(0, &prefix);
+            source.push_str("\n`end_keywords\n");
+        }
+        let header_ext = !options.compile_headers && matches!(path.extension().and_then(|e| e.to_str()), Some("svh" | "vh"));
+        // A complete parse is tried first, so that recovery is only used (and reported) when needed
+        let mut incomplete = false;
+        let mut parsed = parse_sv_str(&source, path, &pre_defines, &incdirs, options.skip_includes, false);
+        let macros_only = parsed.is_err() && header_ext;
+        if macros_only {
+            // Headers are often fragments meant to be included in the middle of something, keep only their macros
+            source = format!("{}{}", &source[..prefix_len], Self::macro_lines(&source[prefix_len..]));
+            parsed = parse_sv_str(&source, path, &pre_defines, &incdirs, options.skip_includes, false);
+        }
+        let (ast, defines) = match parsed {
+            Ok(parsed) => parsed,
+            Err(sv_parser::Error::Parse(pos)) if options.strict_parse => return Err(Self::syntax_error(path, &source[prefix_len..], prefix_len, pos)),
+            Err(sv_parser::Error::Parse(_)) => {
+                incomplete = true;
+                parse_sv_str(&source, path, &pre_defines, &incdirs, options.skip_includes, true)?
+            },
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut skipped_nodes = Vec::new();
+        let (modules_defined, modules_used, modules_nested) = Self::collect_modules(&ast, options.builtin_filter, &mut skipped_nodes)?;
+        let (packages_defined, mut packages_used) = Self::collect_packages(&ast, &mut skipped_nodes)?;
+        let (interfaces_defined, interfaces_used) = Self::collect_interfaces(&ast, &mut skipped_nodes)?;
+        let (programs_defined, programs_used) = Self::collect_programs(&ast, &mut skipped_nodes)?;
+        let (checkers_defined, checkers_used) = Self::collect_checkers(&ast, &mut skipped_nodes)?;
+        let (udps_defined, udps_used) = Self::collect_udps(&ast, &mut skipped_nodes)?;
+        let (configs_defined, configs_used, config_rules) = Self::collect_configs(&ast, &mut skipped_nodes)?;
+        let generic_interface_ports = Self::collect_generic_interface_ports(&ast)?;
+        let interface_connections = Self::collect_interface_connections(&ast)?;
+        let includes = Self::collect_includes(&ast, path);
+        let skipped_includes = if options.skip_includes {
+            let (count, emptied) = Self::collect_skipped_includes(&ast, &source)?;
+            if !emptied.is_empty() {
+                bail!("The body of module {} is only skipped includes, --skip-includes can't be used with this file", emptied.join(", "));
+            }
+            count
+        } else {
+            0
+        };
+        let has_timescale = ast.into_iter().any(|node| matches!(node, RefNode::TimescaleCompilerDirective(_)));
+        let hier_refs = if options.hier_deps {
+            Self::collect_hier_refs(&ast)?
+        } else {
+            HashSet::new()
+        };
+        let macro_instances = if options.macro_instances {
+            Self::collect_macro_instances(&ast, path, &source, &defines)?
+        } else {
+            Vec::new()
+        };
+        let (macros_defined, mut macros_used) = if options.macro_deps || options.propagate_defines {
+            Self::collect_macros(&source, &pre_defines, &defines)
+        } else {
+            (HashSet::new(), HashSet::new())
+        };
+        let (package_symbols, unit_symbols_defined, mut symbols_used) = if options.deep_resolve || options.compilation_unit {
+            let (package_symbols, unit_symbols) = Self::collect_declared_symbols(&ast)?;
+            (package_symbols, unit_symbols, Self::collect_unscoped_identifiers(&ast)?)
+        } else {
+            (HashSet::new(), HashSet::new(), HashSet::new())
+        };
+        if options.uvm {
+            // The simulator provides UVM precompiled, so its names are never looked for in the sources
+            let is_uvm = |name: &String| name.starts_with("uvm_");
+            packages_used.retain(|p| !is_uvm(p));
+            macros_used.retain(|m| !is_uvm(m));
+            symbols_used.retain(|s| !is_uvm(s));
+        }
 
-        let (modules_defined, modules_used) = Self::collect_modules(&ast);
-        let (packages_defined, packages_used) = Self::collect_packages(&ast);
+        let declares_nothing = modules_defined.is_empty() && packages_defined.is_empty() && interfaces_defined.is_empty()
+            && programs_defined.is_empty() && checkers_defined.is_empty() && udps_defined.is_empty()
+            && configs_defined.is_empty() && unit_symbols_defined.is_empty();
+        // Headers declaring a package or module are compiled like any other file, and a .sv file
+        // with only defines is a define provider that must stay in the order
+        let header = macros_only || (declares_nothing && header_ext);
 
         Ok(File {
             name: path.to_owned(),
             modules_defined,
             modules_used,
+            modules_nested,
             packages_defined,
             packages_used,
+            interfaces_defined,
+            interfaces_used,
+            programs_defined,
+            programs_used,
+            checkers_defined,
+            checkers_used,
+            udps_defined,
+            udps_used,
+            configs_defined,
+            configs_used,
+            config_rules,
+            generic_interface_ports,
+            interface_connections,
+            package_symbols,
+            unit_symbols_defined,
+            symbols_used,
+            includes,
+            skipped_includes,
+            has_timescale,
+            hier_refs,
+            protected_regions,
+            skipped_nodes,
+            macro_instances,
+            macros_defined,
+            macros_used,
             defines,
+            ignored_uses: Vec::new(),
+            header,
+            library: false,
+            work_library: None,
+            incomplete,
             ast
         })
     }
 
-    fn collect_modules(ast: &SyntaxTree) -> (HashSet<String>, HashSet<String>) {
+    /// How many identifiers of the file are a name, in the parse with the macros as given
+    pub fn count_identifier(&self, name: &str) -> usize {
+        self.ast.into_iter()
+            .filter(|node| matches!(node, RefNode::SimpleIdentifier(_) | RefNode::EscapedIdentifier(_)))
+            .filter(|node| get_ident_string(&self.ast, node.clone()).is_ok_and(|id| id == name))
+            .count()
+    }
+
+    /// Adds the names another parse of the file found, with other macros defined
+    fn merge(&mut self, other: File) {
+        let sets = [
+            (&mut self.modules_defined, other.modules_defined), (&mut self.modules_used, other.modules_used),
+            (&mut self.modules_nested, other.modules_nested),
+            (&mut self.packages_defined, other.packages_defined), (&mut self.packages_used, other.packages_used),
+            (&mut self.interfaces_defined, other.interfaces_defined), (&mut self.interfaces_used, other.interfaces_used),
+            (&mut self.programs_defined, other.programs_defined), (&mut self.programs_used, other.programs_used),
+            (&mut self.checkers_defined, other.checkers_defined), (&mut self.checkers_used, other.checkers_used),
+            (&mut self.udps_defined, other.udps_defined), (&mut self.udps_used, other.udps_used),
+            (&mut self.configs_defined, other.configs_defined), (&mut self.configs_used, other.configs_used),
+            (&mut self.package_symbols, other.package_symbols), (&mut self.unit_symbols_defined, other.unit_symbols_defined),
+            (&mut self.symbols_used, other.symbols_used), (&mut self.hier_refs, other.hier_refs),
+            (&mut self.macros_defined, other.macros_defined), (&mut self.macros_used, other.macros_used),
+        ];
+        for (set, names) in sets {
+            set.extend(names);
+        }
+        for port in other.generic_interface_ports {
+            if !self.generic_interface_ports.contains(&port) {
+                self.generic_interface_ports.push(port);
+            }
+        }
+        for connection in other.interface_connections {
+            if !self.interface_connections.contains(&connection) {
+                self.interface_connections.push(connection);
+            }
+        }
+        self.protected_regions = self.protected_regions.max(other.protected_regions);
+        for instance in other.macro_instances {
+            if !self.macro_instances.contains(&instance) {
+                self.macro_instances.push(instance);
+            }
+        }
+        for node in other.skipped_nodes {
+            if !self.skipped_nodes.contains(&node) {
+                self.skipped_nodes.push(node);
+            }
+        }
+        self.has_timescale |= other.has_timescale;
+        self.incomplete |= other.incomplete;
+        self.skipped_includes = self.skipped_includes.max(other.skipped_includes);
+        self.includes.extend(other.includes);
+        self.includes.sort();
+        self.includes.dedup();
+        for (name, define) in other.defines {
+            self.defines.entry(name).or_insert(define);
+        }
+        if self.config_rules.default_liblist.is_empty() {
+            self.config_rules.default_liblist = other.config_rules.default_liblist;
+        }
+        for (cell, liblist) in other.config_rules.cell_liblists {
+            self.config_rules.cell_liblists.entry(cell).or_insert(liblist);
+        }
+        for (cell, target) in other.config_rules.cell_uses {
+            self.config_rules.cell_uses.entry(cell).or_insert(target);
+        }
+    }
+
+    /// The macros tested by the `ifdef, `ifndef and `elsif directives of a file
+    fn collect_guards(text: &str) -> Vec<String> {
+        let mut guards = HashSet::new();
+        for line in text.lines() {
+            let line = line.split("//").next().unwrap();
+            for directive in line.split('`').skip(1) {
+                let rest = match directive.strip_prefix("ifdef").or_else(|| directive.strip_prefix("ifndef")).or_else(|| directive.strip_prefix("elsif")) {
+                    Some(rest) if rest.starts_with(char::is_whitespace) => rest.trim_start(),
+                    _ => continue,
+                };
+                let end = rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+                if end > 0 {
+                    guards.insert(rest[..end].to_owned());
+                }
+            }
+        }
+        let mut guards: Vec<_> = guards.into_iter().collect();
+        guards.sort();
+        guards
+    }
+
+    /// Points at the line and column where parsing stopped, the position being a byte offset in that file.
+    /// Offsets in the file itself count the prefix we added in front of its source
+    fn syntax_error(path: &Path, source: &str, prefix_len: usize, pos: Option<(PathBuf, usize)>) -> anyhow::Error {
+        let (err_path, offset) = match pos {
+            Some(pos) => pos,
+            None => return anyhow!("Syntax error in {}", path.display()),
+        };
+        let (text, offset) = if err_path == path {
+            (source.to_owned(), offset.saturating_sub(prefix_len))
+        } else {
+            (std::fs::read_to_string(&err_path).unwrap_or_default(), offset)
+        };
+        let offset = (0..=offset.min(text.len())).rev().find(|&i| text.is_char_boundary(i)).unwrap();
+        let before = &text[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        let snippet = text.lines().nth(line - 1).unwrap_or("").trim_end();
+        anyhow::Error::new(SyntaxError { path: err_path, line, column, snippet: snippet.to_owned() })
+    }
+
+    /// Forgets the uses of the names matching one of the patterns, for cells that no source will ever define
+    pub fn ignore_uses(&mut self, patterns: &[Pattern]) {
+        let ignored_uses = &mut self.ignored_uses;
+        for (kind, used) in [("module", &mut self.modules_used), ("interface", &mut self.interfaces_used), ("program", &mut self.programs_used),
+                             ("checker", &mut self.checkers_used), ("primitive", &mut self.udps_used)] {
+            used.retain(|name| {
+                let ignored = patterns.iter().any(|p| p.matches(name));
+                if ignored {
+                    ignored_uses.push((name.clone(), kind));
+                }
+                !ignored
+            });
+        }
+    }
+
+    /// Returns the sorted list of headers that contributed text to the file, including nested ones
+    fn collect_includes(ast: &SyntaxTree, path: &Path) -> Vec<PathBuf> {
+        let mut includes = HashSet::new();
+
+        for node in ast {
+            if let RefNode::Locate(x) = node {
+                if let Some((origin, _)) = ast.get_origin(x) {
+                    if origin != path && !includes.contains(origin) {
+                        includes.insert(origin.clone());
+                    }
+                }
+            }
+        }
+
+        let mut includes: Vec<_> = includes.into_iter().collect();
+        includes.sort();
+        includes
+    }
+
+    /// Counts the `include directives that weren't followed, and finds the modules whose body has nothing but includes.
+    /// The skipped directives aren't in the tree at all, so they are found in the text and placed with the tree's offsets
+    fn collect_skipped_includes(ast: &SyntaxTree, source: &str) -> Result<(usize, Vec<String>)> {
+        let include_offsets: Vec<usize> = source.match_indices("`include").map(|(offset, _)| offset).collect();
+        let mut emptied = Vec::new();
+        // For the outermost module: its name, where its header ends, and whether it has items
+        let mut module: Option<(String, usize, bool)> = None;
+        // End of the last symbol, the ; closing a module header
+        let mut last_end = 0;
+        let mut depth = 0;
+
+        for event in ast.into_iter().event() {
+            match event {
+                NodeEvent::Enter(RefNode::Symbol(x)) => {
+                    // Offsets in the tree are in the preprocessed text, which lacks the directives
+                    if let Some((_, origin)) = ast.get_origin(&x.nodes.0) {
+                        last_end = origin + x.nodes.0.len;
+                    }
+                }
+                NodeEvent::Enter(RefNode::ModuleDeclaration(x)) => {
+                    depth += 1;
+                    if depth == 1 {
+                        let id = unwrap_node!(x, ModuleIdentifier).map(|id| get_ident_string(ast, id)).transpose()?;
+                        module = Some((id.unwrap_or_default(), 0, false));
+                    }
+                }
+                NodeEvent::Leave(RefNode::ModuleAnsiHeader(_) | RefNode::ModuleNonansiHeader(_)) if depth == 1 => {
+                    if let Some((_, header_end, _)) = &mut module {
+                        *header_end = last_end;
+                    }
+                }
+                NodeEvent::Enter(RefNode::ModuleItem(_) | RefNode::NonPortModuleItem(_)) => {
+                    if let Some((_, _, has_items)) = &mut module {
+                        *has_items = true;
+                    }
+                }
+                NodeEvent::Enter(RefNode::Keyword(x)) if depth == 1 && ast.get_str(&x.nodes.0) == Some("endmodule") => {
+                    let end = ast.get_origin(&x.nodes.0).map_or(0, |(_, origin)| origin);
+                    if let Some((name, header_end, false)) = module.take() {
+                        if include_offsets.iter().any(|&offset| offset >= header_end && offset < end) {
+                            emptied.push(name);
+                        }
+                    }
+                }
+                NodeEvent::Leave(RefNode::ModuleDeclaration(_)) => {
+                    depth -= 1;
+                }
+                _ => (),
+            }
+        }
+        Ok((include_offsets.len(), emptied))
+    }
+
+    /// Blanks out everything but the `define directives, keeping the line breaks so locations stay right
+    fn macro_lines(source: &str) -> String {
+        let mut text = String::with_capacity(source.len());
+        let mut in_define = false;
+        for line in source.lines() {
+            in_define = in_define || line.trim_start().starts_with("`define");
+            if in_define {
+                text.push_str(line);
+            }
+            text.push('\n');
+            // Macro bodies continue on the next line after a backslash
+            in_define = in_define && line.trim_end().ends_with('\\');
+        }
+        text
+    }
+
+    /// Blanks out encrypted IP regions, so the visible parts of the file can still be parsed.
+    /// Returns the number of regions skipped
+    fn strip_protected(source: &mut String) -> usize {
+        const MARKERS: &[(&str, &str)] = &[
+            ("`pragma protect begin_protected", "`pragma protect end_protected"),
+            ("`protected", "`endprotected"),
+        ];
+        let mut regions = 0;
+        for (begin, end) in MARKERS {
+            while let Some(start) = source.find(begin) {
+                let end = source[start..].find(end)
+                    .map(|e| start + e + end.len())
+                    .unwrap_or(source.len());
+                // Keep line breaks and byte offsets so the locations in the rest of the file stay right
+                let blank: String = source[start..end].chars()
+                    .map(|c| if c == '\n' { "\n".to_owned() } else { " ".repeat(c.len_utf8()) })
+                    .collect();
+                source.replace_range(start..end, &blank);
+                regions += 1;
+            }
+        }
+        regions
+    }
+
+    /// Drops the uvm_macros.svh include and defines the `uvm_* macros used as empty, so files parse without a UVM install
+    fn stub_uvm(source: &mut String, pre_defines: &mut Defines) {
+        let mut stubbed_includes = Vec::new();
+        for (start, _) in source.match_indices("`include") {
+            let rest = &source[start+"`include".len()..];
+            let arg = rest.trim_start();
+            for header in ["\"uvm_macros.svh\"", "<uvm_macros.svh>"] {
+                if arg.starts_with(header) {
+                    let end = source.len() - arg.len() + header.len();
+                    stubbed_includes.push(start..end);
+                }
+            }
+        }
+        // Blank with spaces rather than removing, so the offsets of the rest of the text don't change
+        for range in stubbed_includes {
+            let blank = " ".repeat(range.len());
+            source.replace_range(range, &blank);
+        }
+
+        for (start, _) in source.match_indices("`uvm_") {
+            let name = &source[start+1..];
+            let name = &name[..name.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(name.len())];
+            // A macro with no value expands to nothing, arguments included
+            pre_defines.entry(name.to_owned()).or_insert(None);
+        }
+    }
+
+    /// Scans the file's own text for macro definitions, and for uses of macros it doesn't define itself
+    fn collect_macros(text: &str, pre_defines: &Defines, defines: &Defines) -> (HashSet<String>, HashSet<String>) {
+        let mut macros_defined = HashSet::new();
+        let mut macros_used = HashSet::new();
+
+        for line in text.lines() {
+            let line = line.split("//").next().unwrap();
+            let directives = line.split('`').skip(1).map(|d| {
+                let end = d.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(d.len());
+                (&d[..end], d[end..].trim_start())
+            });
+            for (name, rest) in directives {
+                let arg_end = rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+                let arg = &rest[..arg_end];
+                match name {
+                    "define" => { macros_defined.insert(arg.to_owned()); }
+                    "ifdef" | "ifndef" | "elsif" | "undef" => { macros_used.insert(arg.to_owned()); }
+                    _ if COMPILER_DIRECTIVES.contains(&name) || name.is_empty() => (),
+                    _ => { macros_used.insert(name.to_owned()); }
+                }
+            }
+        }
+
+        // Macros from the file's own headers don't need another source
+        let local = |m: &String| defines.contains_key(m) && !pre_defines.contains_key(m);
+        macros_defined.retain(|m| local(m));
+        macros_used.retain(|m| !local(m) && !m.is_empty());
+        (macros_defined, macros_used)
+    }
+
+    fn collect_macro_instances(ast: &SyntaxTree, path: &Path, source: &str, defines: &Defines) -> Result<Vec<(String, String)>> {
+        let mut macro_instances = Vec::new();
+
+        for node in ast {
+            if let RefNode::ModuleInstantiation(x) = node {
+                let id = match unwrap_node!(x, ModuleIdentifier) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                let id_str = get_ident_string(ast, id.clone())?;
+                let locate = match unwrap_node!(id, Locate) {
+                    Some(RefNode::Locate(locate)) => locate,
+                    _ => continue,
+                };
+                if let Some(macro_name) = get_expanding_macro(ast, locate, &id_str, path, source, defines) {
+                    //println!("macro instance: {} from {}", id_str, macro_name);
+                    macro_instances.push((id_str, macro_name));
+                }
+            }
+        }
+
+        macro_instances.sort();
+        Ok(macro_instances)
+    }
+
+    fn collect_modules(ast: &SyntaxTree, builtin_filter: bool, skipped: &mut Vec<String>) -> Result<(HashSet<String>, HashSet<String>, HashSet<String>)> {
         let mut modules_defined = HashSet::new();
         let mut modules_used = HashSet::new();
+        let mut modules_nested = HashSet::new();
+        let mut depth = 0;
 
-        for node in ast {
-            match node {
-                RefNode::ModuleInstantiation(x) => {
-                    let id = unwrap_node!(x, ModuleIdentifier).unwrap();
-                    let id_str = get_ident_string(ast, id).unwrap();
+        for event in ast.into_iter().event() {
+            match event {
+                NodeEvent::Enter(RefNode::ModuleInstantiation(x)) => {
+                    let id_str = ident_or_skip!(ast, skipped, x, ModuleIdentifier);
+                    if builtin_filter && BUILTIN_PRIMITIVES.contains(&id_str.as_str()) {
+                        continue;
+                    }
                     //println!("module used: {}", id_str);
                     modules_used.insert(id_str);
                 }
-                RefNode::ModuleDeclaration(x) => {
-                    let id = unwrap_node!(x, ModuleIdentifier).unwrap();
-                    let id_str = get_ident_string(ast, id).unwrap();
-                    //println!("module decl: {}", id_str);
-                    modules_defined.insert(id_str);
+                NodeEvent::Enter(node @ RefNode::ModuleDeclaration(ModuleDeclaration::ExternNonansi(_) | ModuleDeclaration::ExternAnsi(_))) => {
+                    // Extern prototypes are only forward references, the real definition lives elsewhere
+                    depth += 1;
+                    let id_str = ident_or_skip!(ast, skipped, node, ModuleIdentifier);
+                    //println!("extern module decl: {}", id_str);
+                    modules_used.insert(id_str);
+                }
+                NodeEvent::Enter(RefNode::ModuleDeclaration(x)) => {
+                    depth += 1;
+                    let id_str = ident_or_skip!(ast, skipped, x, ModuleIdentifier);
+                    if depth > 1 {
+                        // Nested modules are only visible inside their parent
+                        //println!("nested module decl: {}", id_str);
+                        modules_nested.insert(id_str);
+                    } else {
+                        //println!("module decl: {}", id_str);
+                        modules_defined.insert(id_str);
+                    }
+                }
+                NodeEvent::Leave(RefNode::ModuleDeclaration(_)) => {
+                    depth -= 1;
+                }
+                NodeEvent::Enter(RefNode::BindTargetScope(x)) => {
+                    let id_str = ident_or_skip!(ast, skipped, x, ModuleIdentifier, InterfaceIdentifier);
+                    //println!("bind target: {}", id_str);
+                    modules_used.insert(id_str);
+                }
+                NodeEvent::Enter(RefNode::BindInstantiation(x)) => {
+                    let id_str = ident_or_skip!(ast, skipped, x, ModuleIdentifier, ProgramIdentifier, InterfaceIdentifier, CheckerIdentifier);
+                    //println!("bound module: {}", id_str);
+                    modules_used.insert(id_str);
                 }
                 _ => (),
             }
         }
 
-        (modules_defined, modules_used)
+        // Instances of nested modules resolve locally
+        modules_used.retain(|m| !modules_nested.contains(m));
+
+        Ok((modules_defined, modules_used, modules_nested))
     }
 
-    fn collect_packages(ast: &SyntaxTree) -> (HashSet<String>, HashSet<String>) {
+    fn collect_packages(ast: &SyntaxTree, skipped: &mut Vec<String>) -> Result<(HashSet<String>, HashSet<String>)> {
         let mut packages_defined = HashSet::new();
         let mut packages_used = HashSet::new();
+        let mut classes_nested = HashSet::new();
+        let mut depth = 0;
+        let mut in_dpi = false;
 
-        for node in ast {
+        for event in ast.into_iter().event() {
+            let node = match event {
+                // import/export "DPI-C" declarations name C functions, not packages
+                NodeEvent::Enter(RefNode::DpiImportExport(_)) => {
+                    in_dpi = true;
+                    continue;
+                }
+                NodeEvent::Leave(RefNode::DpiImportExport(_)) => {
+                    in_dpi = false;
+                    continue;
+                }
+                NodeEvent::Enter(_) if in_dpi => continue,
+                NodeEvent::Enter(node) => node,
+                NodeEvent::Leave(RefNode::PackageDeclaration(_) | RefNode::ClassDeclaration(_) | RefNode::InterfaceClassDeclaration(_)
+                                 | RefNode::ModuleDeclaration(_) | RefNode::InterfaceDeclaration(_) | RefNode::ProgramDeclaration(_)
+                                 | RefNode::CheckerDeclaration(_)) => {
+                    depth -= 1;
+                    continue;
+                }
+                NodeEvent::Leave(_) => continue,
+            };
             match node {
                 RefNode::PackageDeclaration(x) => {
-                    let id = unwrap_node!(x, PackageIdentifier).unwrap();
-                    let id_str = get_ident_string(ast, id).unwrap();
+                    depth += 1;
+                    let id_str = ident_or_skip!(ast, skipped, x, PackageIdentifier);
                     //println!("package decl: {}", id_str);
                     packages_defined.insert(id_str);
                 }
-                RefNode::ClassDeclaration(x) => {
-                    let id = unwrap_node!(x, ClassIdentifier).unwrap();
-                    let id_str = get_ident_string(ast, id).unwrap();
-                    //println!("class decl: {}", id_str);
-                    packages_defined.insert(id_str);
+                RefNode::ClassDeclaration(_) | RefNode::InterfaceClassDeclaration(_) => {
+                    depth += 1;
+                    let id_str = ident_or_skip!(ast, skipped, node, ClassIdentifier);
+                    if depth > 1 {
+                        // Classes inside a package are reached through the package, others are local
+                        //println!("nested class decl: {}", id_str);
+                        classes_nested.insert(id_str);
+                    } else {
+                        //println!("class decl: {}", id_str);
+                        packages_defined.insert(id_str);
+                    }
+                }
+                RefNode::ModuleDeclaration(_) | RefNode::InterfaceDeclaration(_) | RefNode::ProgramDeclaration(_) | RefNode::CheckerDeclaration(_) => {
+                    depth += 1;
+                }
+                RefNode::InterfaceClassType(x) => {
+                    // Appears in implements lists, and in the extends list of interface classes
+                    let id_str = get_ps_class_string(ast, &x.nodes.0)?;
+                    //println!("interface class used: {}", id_str);
+                    packages_used.insert(id_str);
+                }
+                RefNode::TypeDeclarationReserved(x) => {
+                    // Forward typedefs only reference a class defined elsewhere
+                    match x.nodes.1 {
+                        Some(TypeDeclarationKeyword::Class(_)) | Some(TypeDeclarationKeyword::InterfaceClass(_)) | None => {
+                            let id_str = ident_or_skip!(ast, skipped, x, TypeIdentifier);
+                            //println!("forward class typedef: {}", id_str);
+                            packages_used.insert(id_str);
+                        }
+                        _ => (),
+                    }
                 }
                 RefNode::PackageImportItem(x) => {
-                    let id = unwrap_node!(x, PackageIdentifier).unwrap();
-                    let id_str = get_ident_string(ast, id).unwrap();
+                    // Also covers the items of `export pkg::*;` declarations
+                    let id_str = ident_or_skip!(ast, skipped, x, PackageIdentifier);
                     //println!("package used: {}", id_str);
                     packages_used.insert(id_str);
                 }
-                RefNode::ClassScope(x) => {
-                    let id = unwrap_node!(x, ClassIdentifier).unwrap();
-                    let id_str = get_ident_string(ast, id).unwrap();
+                RefNode::PackageScope(PackageScope::Package(x)) => {
+                    // Explicitly scoped references like pkg::NAME, pkg::func() or pkg::prop() in assertions
+                    let id_str = get_ident_string(ast, (&x.nodes.0).into())?;
+                    //println!("package scope used: {}", id_str);
+                    packages_used.insert(id_str);
+                }
+                RefNode::ClassType(x) => {
+                    // Covers class scopes (cls::member, cls#(T)::member), specialized types (cls#(T)) and extends clauses
+                    let id_str = get_ps_class_string(ast, &x.nodes.0)?;
                     //println!("class/package used: {}", id_str);
                     packages_used.insert(id_str);
                 }
@@ -95,7 +783,434 @@ impl File {
             }
         }
 
-        (packages_defined, packages_used)
+        // References to nested classes resolve in their own scope
+        packages_used.retain(|p| !classes_nested.contains(p));
+
+        Ok((packages_defined, packages_used))
+    }
+
+    fn collect_interfaces(ast: &SyntaxTree, skipped: &mut Vec<String>) -> Result<(HashSet<String>, HashSet<String>)> {
+        let mut interfaces_defined = HashSet::new();
+        let mut interfaces_used = HashSet::new();
+
+        for node in ast {
+            match node {
+                RefNode::InterfaceDeclaration(InterfaceDeclaration::ExternNonansi(_) | InterfaceDeclaration::ExternAnsi(_)) => {
+                    // Extern prototypes are only forward references, the real definition lives elsewhere
+                    let id_str = ident_or_skip!(ast, skipped, node, InterfaceIdentifier);
+                    //println!("extern interface decl: {}", id_str);
+                    interfaces_used.insert(id_str);
+                }
+                RefNode::InterfaceDeclaration(x) => {
+                    let id_str = ident_or_skip!(ast, skipped, x, InterfaceIdentifier);
+                    //println!("interface decl: {}", id_str);
+                    interfaces_defined.insert(id_str);
+                }
+                RefNode::InterfaceInstantiation(x) => {
+                    // Outside of interfaces, these are parsed as module instantiations
+                    let id_str = ident_or_skip!(ast, skipped, x, InterfaceIdentifier);
+                    //println!("interface used: {}", id_str);
+                    interfaces_used.insert(id_str);
+                }
+                RefNode::DataTypeVirtual(x) => {
+                    let id_str = ident_or_skip!(ast, skipped, x, InterfaceIdentifier);
+                    //println!("virtual interface used: {}", id_str);
+                    interfaces_used.insert(id_str);
+                }
+                RefNode::InterfacePortHeaderIdentifier(_) | RefNode::InterfacePortDeclaration(_) => {
+                    // The modport, if any, is a separate ModportIdentifier
+                    let id_str = ident_or_skip!(ast, skipped, node, InterfaceIdentifier);
+                    //println!("interface port used: {}", id_str);
+                    interfaces_used.insert(id_str);
+                }
+                _ => (),
+            }
+        }
+
+        Ok((interfaces_defined, interfaces_used))
+    }
+
+    fn collect_programs(ast: &SyntaxTree, skipped: &mut Vec<String>) -> Result<(HashSet<String>, HashSet<String>)> {
+        let mut programs_defined = HashSet::new();
+        let mut programs_used = HashSet::new();
+
+        for node in ast {
+            match node {
+                RefNode::ProgramDeclaration(ProgramDeclaration::ExternNonansi(_) | ProgramDeclaration::ExternAnsi(_)) => {
+                    // Extern prototypes are only forward references, the real definition lives elsewhere
+                    let id_str = ident_or_skip!(ast, skipped, node, ProgramIdentifier);
+                    //println!("extern program decl: {}", id_str);
+                    programs_used.insert(id_str);
+                }
+                RefNode::ProgramDeclaration(x) => {
+                    let id_str = ident_or_skip!(ast, skipped, x, ProgramIdentifier);
+                    //println!("program decl: {}", id_str);
+                    programs_defined.insert(id_str);
+                }
+                RefNode::ProgramInstantiation(x) => {
+                    // Inside modules, these are parsed as module instantiations
+                    let id_str = ident_or_skip!(ast, skipped, x, ProgramIdentifier);
+                    //println!("program used: {}", id_str);
+                    programs_used.insert(id_str);
+                }
+                _ => (),
+            }
+        }
+
+        Ok((programs_defined, programs_used))
+    }
+
+    fn collect_checkers(ast: &SyntaxTree, skipped: &mut Vec<String>) -> Result<(HashSet<String>, HashSet<String>)> {
+        let mut checkers_defined = HashSet::new();
+        let mut checkers_used = HashSet::new();
+
+        for node in ast {
+            match node {
+                RefNode::CheckerDeclaration(x) => {
+                    let id_str = ident_or_skip!(ast, skipped, x, CheckerIdentifier);
+                    //println!("checker decl: {}", id_str);
+                    checkers_defined.insert(id_str);
+                }
+                RefNode::CheckerInstantiation(x) => {
+                    // Most of the time, these are parsed as module instantiations
+                    let id_str = ident_or_skip!(ast, skipped, x, CheckerIdentifier);
+                    //println!("checker used: {}", id_str);
+                    checkers_used.insert(id_str);
+                }
+                _ => (),
+            }
+        }
+
+        Ok((checkers_defined, checkers_used))
+    }
+
+    fn collect_udps(ast: &SyntaxTree, skipped: &mut Vec<String>) -> Result<(HashSet<String>, HashSet<String>)> {
+        let mut udps_defined = HashSet::new();
+        let mut udps_used = HashSet::new();
+
+        for node in ast {
+            match node {
+                RefNode::UdpDeclaration(x) => {
+                    let id_str = ident_or_skip!(ast, skipped, x, UdpIdentifier);
+                    //println!("udp decl: {}", id_str);
+                    udps_defined.insert(id_str);
+                }
+                RefNode::UdpInstantiation(x) => {
+                    // Named UDP instances are parsed as module instantiations
+                    let id_str = ident_or_skip!(ast, skipped, x, UdpIdentifier);
+                    //println!("udp used: {}", id_str);
+                    udps_used.insert(id_str);
+                }
+                _ => (),
+            }
+        }
+
+        Ok((udps_defined, udps_used))
+    }
+
+    fn collect_configs(ast: &SyntaxTree, skipped: &mut Vec<String>) -> Result<(HashSet<String>, HashSet<String>, ConfigRules)> {
+        let mut configs_defined = HashSet::new();
+        let mut configs_used = HashSet::new();
+        let mut rules = ConfigRules::default();
+        let liblist = |clause: &LiblistClause| clause.nodes.1.iter()
+            .map(|lib| get_ident_string(ast, RefNode::from(lib)))
+            .collect::<Result<Vec<_>>>();
+        let lib_cell = |lib: &Option<(LibraryIdentifier, Symbol)>, cell: &CellIdentifier| -> Result<(Option<String>, String)> {
+            let lib = lib.as_ref().map(|(lib, _)| get_ident_string(ast, RefNode::from(lib))).transpose()?;
+            Ok((lib, get_ident_string(ast, RefNode::from(cell))?))
+        };
+
+        for node in ast {
+            match node {
+                RefNode::ConfigDeclaration(x) => {
+                    let id_str = ident_or_skip!(ast, skipped, x, ConfigIdentifier);
+                    //println!("config decl: {}", id_str);
+                    configs_defined.insert(id_str);
+                }
+                RefNode::CellIdentifier(_) | RefNode::TopmoduleIdentifier(_) => {
+                    // Cells only appear in design statements and config rules
+                    let id_str = get_ident_string(ast, node)?;
+                    //println!("config cell used: {}", id_str);
+                    configs_used.insert(id_str);
+                }
+                RefNode::DesignStatement(x) => {
+                    for (lib, cell) in &x.nodes.1 {
+                        let (lib, cell) = lib_cell(lib, cell)?;
+                        rules.cell_uses.entry(cell.clone()).or_insert((lib, cell));
+                    }
+                }
+                RefNode::ConfigRuleStatement(x) => match x {
+                    ConfigRuleStatement::Default(x) => {
+                        if rules.default_liblist.is_empty() {
+                            rules.default_liblist = liblist(&x.nodes.1)?;
+                        }
+                    }
+                    ConfigRuleStatement::CellLib(x) => {
+                        let cell = get_ident_string(ast, RefNode::from(&x.nodes.0.nodes.2))?;
+                        rules.cell_liblists.entry(cell).or_insert(liblist(&x.nodes.1)?);
+                    }
+                    ConfigRuleStatement::CellUse(x) => {
+                        let cell = get_ident_string(ast, RefNode::from(&x.nodes.0.nodes.2))?;
+                        let target = match &x.nodes.1 {
+                            UseClause::Cell(u) => lib_cell(&u.nodes.1, &u.nodes.2)?,
+                            UseClause::CellNamed(u) => lib_cell(&u.nodes.1, &u.nodes.2)?,
+                            UseClause::Named(_) => continue,
+                        };
+                        rules.cell_uses.entry(cell).or_insert(target);
+                    }
+                    ConfigRuleStatement::InstLib(_) | ConfigRuleStatement::InstUse(_) => {
+                        // Files can't tell which instance uses which cell, only cell rules are followed
+                        skipped.push(format!("{} (instance rules aren't supported)", describe_node(ast, node.clone())));
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        Ok((configs_defined, configs_used, rules))
+    }
+
+    fn collect_hier_refs(ast: &SyntaxTree) -> Result<HashSet<String>> {
+        let mut hier_refs = HashSet::new();
+        let mut instances = HashSet::new();
+
+        for node in ast {
+            match node {
+                RefNode::HierarchicalIdentifier(x) => {
+                    // Covers defparam, force/release targets and cross-module references alike
+                    if let Some((first, _, _)) = x.nodes.1.first() {
+                        let id_str = get_ident_string(ast, first.into())?;
+                        //println!("hierarchical ref: {}", id_str);
+                        hier_refs.insert(id_str);
+                    }
+                }
+                RefNode::InstanceIdentifier(x) => {
+                    let id_str = get_ident_string(ast, x.into())?;
+                    instances.insert(id_str);
+                }
+                _ => (),
+            }
+        }
+
+        // Paths through instances of this file stay local
+        hier_refs.retain(|r| !instances.contains(r));
+        Ok(hier_refs)
+    }
+
+    /// Returns (module, port) pairs for ports declared with the generic `interface` keyword
+    fn collect_generic_interface_ports(ast: &SyntaxTree) -> Result<Vec<(String, String)>> {
+        let mut ports = Vec::new();
+        let mut modules = Vec::new();
+
+        for event in ast.into_iter().event() {
+            match event {
+                NodeEvent::Enter(RefNode::ModuleDeclaration(x)) => {
+                    let id = unwrap_node!(x, ModuleIdentifier).map(|id| get_ident_string(ast, id)).transpose()?;
+                    modules.push(id.unwrap_or_default());
+                }
+                NodeEvent::Leave(RefNode::ModuleDeclaration(_)) => {
+                    modules.pop();
+                }
+                NodeEvent::Enter(RefNode::AnsiPortDeclarationNet(x)) => {
+                    if let Some(NetPortHeaderOrInterfacePortHeader::InterfacePortHeader(header)) = &x.nodes.0 {
+                        if let InterfacePortHeader::Interface(_) = **header {
+                            let port = get_ident_string(ast, (&x.nodes.1).into())?;
+                            let module = modules.last().cloned().unwrap_or_default();
+                            //println!("generic interface port: {}.{}", module, port);
+                            ports.push((module, port));
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(ports)
+    }
+
+    /// Collects the named port connections of instances to the interface instances or interface ports of the same module,
+    /// which is what gives a type to the generic interface ports of the instantiated module
+    fn collect_interface_connections(ast: &SyntaxTree) -> Result<Vec<(String, String, String)>> {
+        let mut connections = Vec::new();
+        // For each module being walked, its names with the type they are declared with, and its connections by name
+        let mut types: Vec<HashMap<String, String>> = Vec::new();
+        let mut connected: Vec<Vec<(String, String, String)>> = Vec::new();
+
+        for event in ast.into_iter().event() {
+            match event {
+                NodeEvent::Enter(RefNode::ModuleDeclaration(_)) => {
+                    types.push(HashMap::new());
+                    connected.push(Vec::new());
+                }
+                NodeEvent::Leave(RefNode::ModuleDeclaration(_)) => {
+                    // Instances can be connected before the line declaring them
+                    if let (Some(types), Some(connected)) = (types.pop(), connected.pop()) {
+                        for (module, port, name) in connected {
+                            if let Some(interface) = types.get(&name) {
+                                connections.push((module, port, interface.clone()));
+                            }
+                        }
+                    }
+                }
+                NodeEvent::Enter(RefNode::AnsiPortDeclarationNet(x)) => {
+                    if let (Some(NetPortHeaderOrInterfacePortHeader::InterfacePortHeader(header)), Some(types)) = (&x.nodes.0, types.last_mut()) {
+                        if let InterfacePortHeader::Identifier(header) = &**header {
+                            types.insert(get_ident_string(ast, (&x.nodes.1).into())?, get_ident_string(ast, (&header.nodes.0).into())?);
+                        }
+                    }
+                }
+                NodeEvent::Enter(RefNode::ModuleInstantiation(x)) => {
+                    let (types, connected) = match (types.last_mut(), connected.last_mut()) {
+                        (Some(types), Some(connected)) => (types, connected),
+                        _ => continue,
+                    };
+                    let module = get_ident_string(ast, (&x.nodes.0).into())?;
+                    for instance in x.nodes.2.contents() {
+                        types.insert(get_ident_string(ast, (&instance.nodes.0).into())?, module.clone());
+                        let ports = match &instance.nodes.1.nodes.1 {
+                            Some(ListOfPortConnections::Named(ports)) => ports,
+                            _ => continue,
+                        };
+                        for port in ports.nodes.0.contents() {
+                            if let NamedPortConnection::Identifier(port) = port {
+                                let port_name = get_ident_string(ast, (&port.nodes.2).into())?;
+                                // .bus alone connects the name bus
+                                let name = match &port.nodes.3 {
+                                    Some(paren) => match paren.nodes.1.as_ref().map(|expression| get_ident_string(ast, expression.into())) {
+                                        Some(Ok(name)) => name,
+                                        // Unconnected, or a constant
+                                        _ => continue,
+                                    },
+                                    None => port_name.clone(),
+                                };
+                                connected.push((module.clone(), port_name, name));
+                            }
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(connections)
+    }
+
+    /// Collects the parameters, types, classes and subroutines declared at package scope,
+    /// and those declared directly in the compilation unit ($unit) outside of any design element
+    fn collect_declared_symbols(ast: &SyntaxTree) -> Result<(HashSet<String>, HashSet<String>)> {
+        let mut package_symbols = HashSet::new();
+        let mut unit_symbols = HashSet::new();
+        let mut in_package = false;
+        let mut elements = 0;
+        let mut depth = 0;
+
+        for event in ast.into_iter().event() {
+            let id = match event {
+                NodeEvent::Enter(RefNode::PackageDeclaration(_)) => {
+                    in_package = true;
+                    elements += 1;
+                    None
+                }
+                NodeEvent::Leave(RefNode::PackageDeclaration(_)) => {
+                    in_package = false;
+                    elements -= 1;
+                    None
+                }
+                NodeEvent::Enter(RefNode::ModuleDeclaration(_) | RefNode::InterfaceDeclaration(_) | RefNode::ProgramDeclaration(_)
+                                 | RefNode::CheckerDeclaration(_) | RefNode::UdpDeclaration(_) | RefNode::ConfigDeclaration(_)) => {
+                    elements += 1;
+                    None
+                }
+                NodeEvent::Leave(RefNode::ModuleDeclaration(_) | RefNode::InterfaceDeclaration(_) | RefNode::ProgramDeclaration(_)
+                                 | RefNode::CheckerDeclaration(_) | RefNode::UdpDeclaration(_) | RefNode::ConfigDeclaration(_)) => {
+                    elements -= 1;
+                    None
+                }
+                NodeEvent::Enter(node @ (RefNode::ClassDeclaration(_) | RefNode::InterfaceClassDeclaration(_))) => {
+                    depth += 1;
+                    (depth == 1).then(|| unwrap_node!(node, ClassIdentifier)).flatten()
+                }
+                NodeEvent::Enter(RefNode::FunctionDeclaration(x)) => {
+                    depth += 1;
+                    (depth == 1).then(|| unwrap_node!(x, FunctionIdentifier)).flatten()
+                }
+                NodeEvent::Enter(RefNode::TaskDeclaration(x)) => {
+                    depth += 1;
+                    (depth == 1).then(|| unwrap_node!(x, TaskIdentifier)).flatten()
+                }
+                NodeEvent::Leave(RefNode::ClassDeclaration(_) | RefNode::InterfaceClassDeclaration(_)
+                                 | RefNode::FunctionDeclaration(_) | RefNode::TaskDeclaration(_)) => {
+                    depth -= 1;
+                    None
+                }
+                NodeEvent::Enter(RefNode::ParamAssignment(x)) if depth == 0 => Some((&x.nodes.0).into()),
+                NodeEvent::Enter(RefNode::TypeAssignment(x)) if depth == 0 => Some((&x.nodes.0).into()),
+                NodeEvent::Enter(RefNode::EnumNameDeclaration(x)) if depth == 0 => Some((&x.nodes.0).into()),
+                NodeEvent::Enter(RefNode::TypeDeclaration(x)) if depth == 0 => match x {
+                    TypeDeclaration::DataType(t) => Some((&t.nodes.2).into()),
+                    TypeDeclaration::Interface(t) => Some((&t.nodes.5).into()),
+                    TypeDeclaration::Reserved(t) => Some((&t.nodes.2).into()),
+                },
+                _ => None,
+            };
+            if let Some(id) = id {
+                if in_package && elements == 1 {
+                    let id_str = get_ident_string(ast, id)?;
+                    //println!("package symbol: {}", id_str);
+                    package_symbols.insert(id_str);
+                } else if elements == 0 {
+                    let id_str = get_ident_string(ast, id)?;
+                    //println!("$unit symbol: {}", id_str);
+                    unit_symbols.insert(id_str);
+                }
+            }
+        }
+
+        Ok((package_symbols, unit_symbols))
+    }
+
+    /// Collects identifiers that may refer to a package symbol without an explicit scope
+    fn collect_unscoped_identifiers(ast: &SyntaxTree) -> Result<HashSet<String>> {
+        let mut symbols_used = HashSet::new();
+
+        for node in ast {
+            let id = match node {
+                RefNode::PsOrHierarchicalTfIdentifierPackageScope(x) if x.nodes.0.is_none() => Some((&x.nodes.1).into()),
+                RefNode::PsParameterIdentifierScope(x) if x.nodes.0.is_none() => Some((&x.nodes.1).into()),
+                RefNode::PsTypeIdentifier(x) if x.nodes.0.is_none() => Some((&x.nodes.1).into()),
+                RefNode::DataTypeType(x) if x.nodes.0.is_none() => Some((&x.nodes.1).into()),
+                RefNode::NetDeclarationNetTypeIdentifier(x) => Some((&x.nodes.0).into()),
+                RefNode::PrimaryHierarchical(x) => {
+                    let unscoped = match &x.nodes.0 {
+                        None => true,
+                        Some(ClassQualifierOrPackageScope::ClassQualifier(q)) => q.nodes.0.is_none() && q.nodes.1.is_none(),
+                        Some(ClassQualifierOrPackageScope::PackageScope(_)) => false,
+                    };
+                    let hier = &x.nodes.1;
+                    if unscoped && hier.nodes.0.is_none() && hier.nodes.1.is_empty() {
+                        Some((&hier.nodes.2).into())
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            };
+            if let Some(id) = id {
+                let id_str = get_ident_string(ast, id)?;
+                //println!("unscoped identifier: {}", id_str);
+                symbols_used.insert(id_str);
+            }
+        }
+
+        Ok(symbols_used)
+    }
+}
+
+/// Returns the name of the undefined macro that made parsing fail, if that is the cause
+pub fn missing_define(err: &anyhow::Error) -> Option<&str> {
+    match err.downcast_ref::<sv_parser::Error>() {
+        Some(sv_parser::Error::DefineNotFound(name)) => Some(name),
+        _ => None,
     }
 }
 
@@ -113,15 +1228,73 @@ impl PartialEq for File {
 
 impl Eq for File {}
 
-fn get_ident_string(ast: &SyntaxTree, node: RefNode) -> Option<String> {
+fn get_ident_string(ast: &SyntaxTree, node: RefNode) -> Result<String> {
     // unwrap_node! can take multiple types
-    match unwrap_node!(node, SimpleIdentifier, EscapedIdentifier) {
+    let id_str = match unwrap_node!(node.clone(), SimpleIdentifier, EscapedIdentifier) {
         Some(RefNode::SimpleIdentifier(x)) => {
             ast.get_str(&x.nodes.0).map(|f| f.to_owned())
         }
         Some(RefNode::EscapedIdentifier(x)) => {
-            ast.get_str(&x.nodes.0).map(|f| f.to_owned())
+            // \foo and foo are the same identifier, so compare names without the escape
+            ast.get_str(&x.nodes.0).map(|f| f.trim_start_matches('\\').trim_end().to_owned())
         }
         _ => None,
+    };
+    id_str.with_context(|| format!("No identifier text in {}", describe_node(ast, node)))
+}
+
+// Kind and source location of a node, for error messages
+fn describe_node(ast: &SyntaxTree, node: RefNode) -> String {
+    let kind = format!("{:?}", node).split('(').next().unwrap_or_default().to_owned();
+    let location = match unwrap_node!(node, Locate) {
+        Some(RefNode::Locate(locate)) => match ast.get_origin(locate) {
+            Some((origin, pos)) => match std::fs::read(origin) {
+                Ok(text) => {
+                    let line = text[..pos.min(text.len())].iter().filter(|&&c| c == b'\n').count() + 1;
+                    format!(" at {}:{}", origin.display(), line)
+                }
+                Err(_) => format!(" at {} offset {}", origin.display(), pos),
+            },
+            None => " in a macro expansion".to_owned(),
+        },
+        _ => String::new(),
+    };
+    kind + &location
+}
+
+// Names the macro whose expansion produced the identifier at locate, if any
+fn get_expanding_macro(ast: &SyntaxTree, locate: &Locate, id: &str, path: &Path, source: &str, defines: &Defines) -> Option<String> {
+    let texts = defines.values().flatten().filter_map(|d| Some((d, d.text.as_ref()?)));
+    match ast.get_origin(locate) {
+        // Only the text of command line defines has no origin
+        None => texts.filter(|(_, t)| t.origin.is_none() && t.text.contains(id))
+            .map(|(d, _)| d.identifier.clone())
+            .next(),
+        Some((origin, pos)) => {
+            // Expanded text maps to the start of the macro body, then runs past its end when arguments are longer
+            let (define, range) = texts.filter_map(|(d, t)| Some((d, t.origin.as_ref()?)))
+                .filter(|(_, (p, r))| p == origin && r.begin <= pos)
+                .max_by_key(|(_, (_, r))| r.begin)
+                .map(|(d, (_, r))| (d, r))?;
+            let literal = |text: &str| text.get(pos..pos + id.len()) == Some(id);
+            let is_literal = if origin == path {
+                literal(source)
+            } else {
+                std::fs::read_to_string(origin).is_ok_and(|text| literal(&text))
+            };
+            if pos < range.end || !is_literal {
+                Some(define.identifier.clone())
+            } else {
+                None
+            }
+        }
+    }
+}
+
+// A pkg::cls reference depends on the package rather than on the bare class
+fn get_ps_class_string(ast: &SyntaxTree, x: &PsClassIdentifier) -> Result<String> {
+    match &x.nodes.0 {
+        Some(PackageScope::Package(p)) => get_ident_string(ast, (&p.nodes.0).into()),
+        _ => get_ident_string(ast, (&x.nodes.1).into()),
     }
 }
@@ -0,0 +1,382 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, bail, Context, Result};
+use glob::Pattern;
+use walkdir::{DirEntry, WalkDir};
+
+/// Sources, include paths and defines gathered from the command line and filelists
+#[derive(Default)]
+pub struct Inputs {
+    pub sources: Vec<PathBuf>,
+    pub incdirs: Vec<PathBuf>,
+    pub defines: Vec<String>,
+    /// Expand ? and [...] in sources too, not only patterns with a *
+    pub glob: bool,
+    pub allow_empty_glob: bool,
+    /// Leave unset $VARs in filelists as they are instead of failing
+    pub allow_unset_env: bool,
+    /// Directories searched for the modules no source defines, as -y
+    pub library_dirs: Vec<PathBuf>,
+    pub libext: Vec<String>,
+    /// Files only compiled if something uses them, as -v
+    pub library_files: Vec<PathBuf>,
+    pub scan_roots: Vec<PathBuf>,
+}
+
+impl Inputs {
+    /// Adds a source file, or the values of simulator style +define+ and +incdir+ plusargs
+    pub fn add_arg(&mut self, arg: &OsStr) -> Result<()> {
+        match arg.to_str() {
+            Some(plusarg) if plusarg.starts_with('+') => {
+                if let Some(defines) = plusarg.strip_prefix("+define+") {
+                    self.defines.extend(split_plusarg(defines).map(str::to_owned));
+                } else if let Some(dirs) = plusarg.strip_prefix("+incdir+") {
+                    self.incdirs.extend(split_plusarg(dirs).map(PathBuf::from));
+                } else if let Some(exts) = plusarg.strip_prefix("+libext+") {
+                    self.libext.extend(split_plusarg(exts).map(|e| e.trim_start_matches('.').to_owned()));
+                } else {
+                    warning!("input", "ignoring unknown plusarg {}", plusarg);
+                }
+            }
+            _ => self.add_source(PathBuf::from(arg))?,
+        }
+        Ok(())
+    }
+
+    /// Adds a source file, or the files matching it if it is a glob pattern
+    fn add_source(&mut self, path: PathBuf) -> Result<()> {
+        let pattern = match path.to_str() {
+            Some(p) if p.contains('*') || (self.glob && p.contains(['?', '['])) => p,
+            _ => {
+                if !self.sources.contains(&path) {
+                    self.sources.push(path);
+                }
+                return Ok(())
+            }
+        };
+        let mut found = false;
+        for entry in glob::glob(pattern).with_context(|| format!("Invalid glob pattern {}", pattern))? {
+            let entry = entry?;
+            found = true;
+            if entry.is_file() && !self.sources.contains(&entry) {
+                self.sources.push(entry);
+            }
+        }
+        if !found && !self.allow_empty_glob {
+            bail!("No files match {}", pattern);
+        }
+        Ok(())
+    }
+
+    /// Adds the sources and plusargs of a filelist, and those of the filelists it references in place.
+    /// Its paths are relative to the current directory, or to the filelist itself if relative is set
+    pub fn add_filelist(&mut self, path: &Path, relative: bool) -> Result<()> {
+        self.add_nested_filelist(path, relative, &mut Vec::new())
+    }
+
+    fn add_nested_filelist(&mut self, path: &Path, relative: bool, stack: &mut Vec<PathBuf>) -> Result<()> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read filelist {}", path.display()))?;
+        let canonical = std::fs::canonicalize(path)?;
+        if stack.contains(&canonical) {
+            let cycle: Vec<_> = stack.iter().chain([&canonical]).map(|f| f.display().to_string()).collect();
+            bail!("Filelists include each other: {}", cycle.join(" -> "));
+        }
+        stack.push(canonical);
+
+        let base = if relative { path.parent() } else { None };
+        let resolve = |p: &str| match base {
+            Some(base) if Path::new(p).is_relative() => base.join(p),
+            _ => PathBuf::from(p),
+        };
+        let lines = text.lines().enumerate()
+            .map(|(i, line)| {
+                let line = expand_env(strip_comment(line), self.allow_unset_env)
+                    .map_err(|var| anyhow!("{}:{}: environment variable {} is not set", path.display(), i + 1, var))?;
+                split_words(&line).with_context(|| format!("{}:{}: unclosed quote", path.display(), i + 1))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut words = lines.iter().flatten().map(String::as_str);
+        while let Some(word) = words.next() {
+            match word {
+                "-f" | "-F" => {
+                    let nested = words.next().with_context(|| format!("{}: missing filelist after {}", path.display(), word))?;
+                    self.add_nested_filelist(&resolve(nested), word == "-F", stack)?;
+                }
+                "-v" => {
+                    let file = words.next().with_context(|| format!("{}: missing library file after -v", path.display()))?;
+                    self.library_files.push(resolve(file));
+                }
+                "-y" => {
+                    let dir = words.next().with_context(|| format!("{}: missing directory after -y", path.display()))?;
+                    self.library_dirs.push(resolve(dir));
+                }
+                // The language is detected from the file anyway
+                "-sverilog" | "-sv" => (),
+                _ if word.starts_with('-') => {
+                    warning!(file: path, "input", "{}: ignoring unsupported option {}", path.display(), word);
+                }
+                _ if word.starts_with("+incdir+") => {
+                    self.incdirs.extend(split_plusarg(&word["+incdir+".len()..]).map(resolve));
+                }
+                _ if word.starts_with('+') => self.add_arg(OsStr::new(word))?,
+                _ => self.add_source(resolve(word))?,
+            }
+        }
+
+        stack.pop();
+        Ok(())
+    }
+
+    /// Finds the file of a library directory named after a module, with one of the +libext+ extensions
+    pub fn find_library_file(&self, module: &str) -> Option<PathBuf> {
+        let default_exts = ["v".to_owned(), "sv".to_owned()];
+        let exts = if self.libext.is_empty() { &default_exts[..] } else { &self.libext };
+        self.library_dirs.iter()
+            .flat_map(|dir| exts.iter().map(move |ext| dir.join(format!("{}.{}", module, ext))))
+            .find(|path| path.is_file())
+    }
+
+    /// Adds the sources read from stdin, one path per line
+    pub fn add_stdin(&mut self) -> Result<()> {
+        let text = std::io::read_to_string(std::io::stdin()).context("Failed to read sources from stdin")?;
+        // Unlike filelists, paths may contain spaces
+        for line in text.lines().map(|line| strip_comment(line).trim()).filter(|line| !line.is_empty()) {
+            self.add_arg(OsStr::new(line))?;
+        }
+        Ok(())
+    }
+
+    /// Adds the files with one of the extensions found under a directory, returns how many were found
+    pub fn add_scan(&mut self, dir: &Path, extensions: &[&str]) -> usize {
+        let is_hidden = |e: &DirEntry| e.depth() > 0 && e.file_name().to_string_lossy().starts_with('.');
+        let has_extension = |e: &DirEntry| e.path().extension().and_then(|x| x.to_str()).is_some_and(|x| extensions.contains(&x));
+        let start = self.sources.len();
+        self.scan_roots.push(dir.to_owned());
+        // Walkdir reports symlink loops as errors instead of following them
+        for entry in WalkDir::new(dir).follow_links(true).sort_by_file_name().into_iter().filter_entry(|e| !is_hidden(e)) {
+            match entry {
+                Ok(entry) if entry.file_type().is_file() && has_extension(&entry) => self.sources.push(entry.into_path()),
+                Ok(_) => (),
+                Err(err) => warning!("input", "while scanning {}: {}", dir.display(), err),
+            }
+        }
+        self.sources.len() - start
+    }
+
+    /// Include paths guessed from the directories of the sources, and those with headers under --scan roots
+    pub fn auto_incdirs(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        let mut add = |dir: &Path| {
+            if !dirs.iter().any(|d| d == dir) {
+                dirs.push(dir.to_owned());
+            }
+        };
+        for source in &self.sources {
+            add(source.parent().unwrap_or_else(|| Path::new("")));
+        }
+        let is_header = |e: &DirEntry| e.file_type().is_file()
+            && e.path().extension().and_then(|x| x.to_str()).is_some_and(|x| x == "svh" || x == "vh");
+        for root in &self.scan_roots {
+            for entry in WalkDir::new(root).follow_links(true).sort_by_file_name().into_iter().filter_map(|e| e.ok()).filter(is_header) {
+                add(entry.path().parent().unwrap());
+            }
+        }
+        dirs
+    }
+
+    /// Drops the sources listed twice, even through different paths or symlinks. Returns the dropped paths
+    pub fn dedupe(&mut self) -> Vec<PathBuf> {
+        let mut seen = HashSet::new();
+        let mut duplicates = Vec::new();
+        self.sources.retain(|source| {
+            let canonical = std::fs::canonicalize(source).unwrap_or_else(|_| source.clone());
+            if seen.insert(canonical) {
+                true
+            } else {
+                duplicates.push(source.clone());
+                false
+            }
+        });
+        duplicates
+    }
+
+    /// Drops the sources and library files that don't exist, returns their paths
+    pub fn take_missing(&mut self) -> Vec<PathBuf> {
+        let mut missing = Vec::new();
+        for list in [&mut self.sources, &mut self.library_files] {
+            list.retain(|source| {
+                if source.exists() {
+                    true
+                } else {
+                    missing.push(source.clone());
+                    false
+                }
+            });
+        }
+        missing
+    }
+
+    /// Drops the sources whose relative or absolute path matches one of the patterns, returns how many were dropped
+    pub fn exclude(&mut self, patterns: &[Pattern]) -> usize {
+        let excluded = |path: &Path| {
+            let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+            patterns.iter().any(|p| p.matches_path(path) || p.matches_path(&absolute))
+        };
+        let count = self.sources.len();
+        self.sources.retain(|s| !excluded(s));
+        count - self.sources.len()
+    }
+}
+
+/// Strips a // or # comment from a line, unless it is in double quotes
+pub fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            '/' if !quoted && line[i + 1..].starts_with('/') => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+/// Splits a line on whitespace, except in double quotes where \" and \\ are a quote and a backslash.
+/// Returns None if a quote isn't closed
+fn split_words(line: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => word.push(chars.next()?),
+                        c => word.push(c),
+                    }
+                }
+            }
+            _ if c.is_whitespace() => words.extend(word.take()),
+            _ => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Some(words)
+}
+
+/// Library names with the files in them, from a IEEE 1800 library map
+pub struct LibraryMap {
+    libraries: Vec<(String, Vec<Pattern>)>,
+}
+
+impl LibraryMap {
+    pub fn load(path: &Path) -> Result<LibraryMap> {
+        let mut map = LibraryMap { libraries: Vec::new() };
+        map.add_file(path, &mut Vec::new())?;
+        Ok(map)
+    }
+
+    fn add_file(&mut self, path: &Path, stack: &mut Vec<PathBuf>) -> Result<()> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read library map {}", path.display()))?;
+        let canonical = std::fs::canonicalize(path)?;
+        if stack.contains(&canonical) {
+            bail!("Library map {} includes itself", path.display());
+        }
+        stack.push(canonical);
+        let base = std::path::absolute(path)?.parent().unwrap().to_owned();
+        let text: String = text.lines().map(|line| line.split("//").next().unwrap()).collect::<Vec<_>>().join("\n");
+        for statement in text.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+            let (keyword, rest) = statement.split_once(char::is_whitespace).unwrap_or((statement, ""));
+            match keyword {
+                "library" => {
+                    let (name, specs) = rest.trim().split_once(char::is_whitespace).unwrap_or((rest.trim(), ""));
+                    // Only the file specs matter for ordering, -incdir lists come after them
+                    let specs = specs.split(" -incdir").next().unwrap();
+                    let patterns = specs.split(',').map(str::trim).filter(|s| !s.is_empty())
+                        .map(|spec| Self::spec_pattern(&base, spec))
+                        .collect::<Result<_>>()
+                        .with_context(|| format!("{}: invalid file spec in library {}", path.display(), name))?;
+                    self.libraries.push((name.to_owned(), patterns));
+                }
+                "include" => self.add_file(&base.join(rest.trim()), stack)?,
+                _ => bail!("{}: expected library or include, found {}", path.display(), keyword),
+            }
+        }
+        stack.pop();
+        Ok(())
+    }
+
+    /// File specs are globs relative to the map, where ... matches any number of directories
+    fn spec_pattern(base: &Path, spec: &str) -> Result<Pattern> {
+        let mut spec = spec.replace("...", "**");
+        if spec.ends_with('/') {
+            spec.push('*');
+        }
+        if Path::new(&spec).is_relative() {
+            spec = format!("{}/{}", Pattern::escape(&base.to_string_lossy()), spec.trim_start_matches("./"));
+        }
+        Ok(Pattern::new(&spec)?)
+    }
+
+    /// The first library with a spec matching the file, or work
+    pub fn library_of(&self, path: &Path) -> String {
+        let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+        self.libraries.iter()
+            .find(|(_, patterns)| patterns.iter().any(|p| p.matches_path(&absolute)))
+            .map_or_else(|| "work".to_owned(), |(name, _)| name.clone())
+    }
+}
+
+/// Expands $VAR and ${VAR}, with $$ for a literal $. Returns the name of the first unset variable as error
+fn expand_env(line: &str, allow_unset: bool) -> std::result::Result<String, String> {
+    let mut expanded = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(pos) = rest.find('$') {
+        expanded.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+            continue
+        }
+        let (name, len) = match rest.strip_prefix('{').and_then(|r| r.find('}').map(|end| &r[..end])) {
+            Some(name) => (name, name.len() + 2),
+            None => {
+                let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+                (&rest[..end], end)
+            }
+        };
+        match std::env::var(name) {
+            Ok(value) if !name.is_empty() => expanded.push_str(&value),
+            _ if name.is_empty() || allow_unset => {
+                expanded.push('$');
+                expanded.push_str(&rest[..len]);
+            }
+            _ => return Err(name.to_owned()),
+        }
+        rest = &rest[len..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Splits the values of a plusarg on '+', except inside parentheses like in +define+INC(x)=(x+1)
+pub fn split_plusarg(values: &str) -> impl Iterator<Item=&str> {
+    let mut depth = 0;
+    values.split(move |c| {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => (),
+        }
+        c == '+' && depth == 0
+    }).filter(|v| !v.is_empty())
+}
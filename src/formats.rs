@@ -0,0 +1,869 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use crate::{Graph, apply_template, canonical, quote, relative_path, units_defined, units_used};
+use crate::quote::Quote;
+use crate::file::File;
+
+/// A file of the order, with its library from --libmap
+pub struct Source {
+    /// The path as it was found, to place the file
+    pub name: PathBuf,
+    pub path: String,
+    pub library: Option<String>,
+    /// Found in a -y directory rather than listed
+    pub searched: bool,
+}
+
+/// What the scripts for the tools pass besides the files
+pub struct Setup {
+    pub incdirs: Vec<String>,
+    /// As NAME or NAME=VALUE
+    pub defines: Vec<String>,
+    pub library_dirs: Vec<String>,
+    /// The +libext+ extensions, v and sv if there are none
+    pub libext: Vec<String>,
+    pub tops: Vec<String>,
+}
+
+/// The language of a file for the tools, from its extension
+fn language(path: &str) -> &'static str {
+    match Path::new(path).extension().and_then(|x| x.to_str()) {
+        Some("vhd" | "vhdl") => "vhdl",
+        Some("v" | "vh") => "verilog",
+        _ => "systemverilog",
+    }
+}
+
+/// Graphviz graph with an edge from each file to the files it depends on, roots in bold
+pub fn dot(output: &mut String, graph: &Graph, files: &[&File], edge_labels: bool,
+           output_name: &dyn Fn(&Path) -> Result<String>) -> Result<()> {
+    let listed: HashSet<&File> = files.iter().copied().collect();
+    let used: HashSet<&File> = files.iter().flat_map(|f| &graph.deps[f]).copied().collect();
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+    for &file in files {
+        let name = output_name(&file.name)?;
+        for &dep in graph.deps[file].iter().filter(|dep| listed.contains(*dep)) {
+            let mut label = String::new();
+            if edge_labels {
+                let reasons: Vec<_> = graph.reasons[&(file, dep)].iter().map(|r| format!("{} {}", r.kind, r.name)).collect();
+                label = reasons.join("\n");
+            }
+            edges.push((name.clone(), output_name(&dep.name)?, label));
+        }
+        nodes.push((name, !used.contains(file)));
+    }
+    nodes.sort_unstable();
+    edges.sort_unstable();
+
+    writeln!(output, "digraph dependencies {{")?;
+    writeln!(output, "    node [shape=box];")?;
+    for (name, root) in &nodes {
+        let style = if *root { " [style=bold]" } else { "" };
+        writeln!(output, "    {}{};", dot_string(name), style)?;
+    }
+    for (from, to, label) in &edges {
+        let label = if label.is_empty() { String::new() } else { format!(" [label={}]", dot_string(label)) };
+        writeln!(output, "    {} -> {}{};", dot_string(from), dot_string(to), label)?;
+    }
+    writeln!(output, "}}")?;
+    Ok(())
+}
+
+/// Quotes an ID, newlines become line breaks in labels
+fn dot_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+/// Mermaid flowchart of the dependencies, labeled with the file stems.
+/// Past max_nodes files, those a file depends on are summarized in a single node
+pub fn mermaid(output: &mut String, graph: &Graph, files: &[&File], max_nodes: Option<usize>,
+               output_name: &dyn Fn(&Path) -> Result<String>) -> Result<()> {
+    let listed: HashSet<&File> = files.iter().copied().collect();
+    let used: HashSet<&File> = files.iter().flat_map(|f| &graph.deps[f]).copied().collect();
+    // IDs follow the sorted paths, so they only change when files come and go
+    let mut sorted = files.iter().map(|&file| Ok((output_name(&file.name)?, file))).collect::<Result<Vec<_>>>()?;
+    sorted.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    let ids: HashMap<&File, usize> = sorted.iter().enumerate().map(|(id, (_, file))| (*file, id)).collect();
+    let deps = |file: &File| {
+        let mut deps: Vec<&File> = graph.deps[file].iter().copied().filter(|dep| listed.contains(dep)).collect();
+        deps.sort_unstable_by_key(|dep| ids[dep]);
+        deps
+    };
+
+    // Breadth first from all the roots, which are always shown, then from what only cycles reach
+    let max_nodes = max_nodes.unwrap_or(usize::MAX);
+    let mut kept: HashSet<&File> = sorted.iter().map(|(_, file)| *file).filter(|file| !used.contains(file)).collect();
+    let mut queue: std::collections::VecDeque<_> = sorted.iter().map(|(_, file)| *file).filter(|file| kept.contains(file)).collect();
+    let mut cycle_starts = sorted.iter().map(|(_, file)| *file).filter(|file| used.contains(file));
+    loop {
+        while let Some(file) = queue.pop_front() {
+            for dep in deps(file) {
+                if kept.len() < max_nodes && kept.insert(dep) {
+                    queue.push_back(dep);
+                }
+            }
+        }
+        match cycle_starts.find(|file| !kept.contains(file)) {
+            Some(start) if kept.len() < max_nodes => {
+                kept.insert(start);
+                queue.push_back(start);
+            }
+            _ => break,
+        }
+    }
+
+    writeln!(output, "graph TD")?;
+    for (name, file) in sorted.iter().filter(|(_, file)| kept.contains(file)) {
+        let stem = Path::new(name).file_stem().unwrap_or_default().to_string_lossy();
+        writeln!(output, "    n{}[{}]", ids[file], mermaid_string(&stem))?;
+    }
+    for (_, file) in sorted.iter().filter(|(_, file)| kept.contains(file)) {
+        let mut collapsed = HashSet::new();
+        for dep in deps(file) {
+            if kept.contains(dep) {
+                writeln!(output, "    n{} --> n{}", ids[file], ids[dep])?;
+            } else {
+                let mut stack = vec![dep];
+                while let Some(hidden) = stack.pop() {
+                    if collapsed.insert(hidden) {
+                        stack.extend(deps(hidden).into_iter().filter(|dep| !kept.contains(dep)));
+                    }
+                }
+            }
+        }
+        if !collapsed.is_empty() {
+            let plural = if collapsed.len() == 1 { "" } else { "s" };
+            writeln!(output, "    n{}_more[{}]", ids[file], mermaid_string(&format!("{} more file{}", collapsed.len(), plural)))?;
+            writeln!(output, "    n{0} --> n{0}_more", ids[file])?;
+        }
+    }
+    let roots: Vec<_> = sorted.iter().filter(|(_, file)| kept.contains(file) && !used.contains(file)).map(|(_, file)| format!("n{}", ids[file])).collect();
+    if !roots.is_empty() {
+        writeln!(output, "    classDef root stroke-width:3px")?;
+        writeln!(output, "    class {} root", roots.join(","))?;
+    }
+    Ok(())
+}
+
+/// Quotes a label, with an entity for the quotes that can't be escaped
+fn mermaid_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "#quot;"))
+}
+
+/// The nodes.csv and edges.csv tables of the graph, with a row for each reason of an edge
+pub fn csv(graph: &Graph, files: &[&File], output_name: &dyn Fn(&Path) -> Result<String>) -> Result<(String, String)> {
+    let listed: HashSet<&File> = files.iter().copied().collect();
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+    for &file in files {
+        let name = output_name(&file.name)?;
+        for &dep in graph.deps[file].iter().filter(|dep| listed.contains(*dep)) {
+            let dep_name = output_name(&dep.name)?;
+            for reason in &graph.reasons[&(file, dep)] {
+                edges.push(vec![name.clone(), dep_name.clone(), reason.name.clone(), reason.kind.to_owned()]);
+            }
+        }
+        nodes.push(vec![name, units_defined(file).count().to_string(), file.packages_defined.len().to_string()]);
+    }
+    nodes.sort_unstable();
+    edges.sort_unstable();
+    edges.dedup();
+
+    let table = |header: &[&str], rows: &[Vec<String>]| {
+        let mut table = header.join(",") + "\n";
+        for row in rows {
+            table += &row.iter().map(|field| quote::csv(field)).collect::<Vec<_>>().join(",");
+            table += "\n";
+        }
+        table
+    };
+    Ok((table(&["path", "modules_defined", "packages_defined"], &nodes), table(&["from", "to", "reason_symbol", "reason_kind"], &edges)))
+}
+
+/// Makefile fragment with the files in order, and a rule per file for its object
+pub fn make(output: &mut String, graph: &Graph, files: &[&File], sources: &[Source], object_template: &str,
+            output_name: &dyn Fn(&Path) -> Result<String>) -> Result<()> {
+    write!(output, "SV_SOURCES_ORDERED :=")?;
+    for source in sources {
+        write!(output, " \\\n    {}", quote::make(&source.path))?;
+    }
+    writeln!(output)?;
+    for (file, object) in objects(files, object_template, output_name)? {
+        let name = output_name(&file.name)?;
+        let mut prerequisites = sorted_paths(graph.deps[file].iter().map(|dep| output_name(&dep.name)))?;
+        prerequisites.extend(sorted_paths(file.includes.iter().map(|include| output_name(include)))?);
+        prerequisites.insert(0, name.clone());
+        let prerequisites: Vec<_> = prerequisites.iter().map(|p| quote::make(p)).collect();
+        writeln!(output, "\n{}: {}", quote::make(&object), prerequisites.join(" "))?;
+    }
+    Ok(())
+}
+
+/// The --object-template target of each file, which must not be the same for two files
+fn objects<'f>(files: &[&'f File], object_template: &str, output_name: &dyn Fn(&Path) -> Result<String>) -> Result<Vec<(&'f File, String)>> {
+    let mut built_by: HashMap<String, String> = HashMap::new();
+    let mut objects = Vec::new();
+    for &file in files {
+        let name = output_name(&file.name)?;
+        let object = apply_template(object_template, &name);
+        if let Some(other) = built_by.insert(object.clone(), name.clone()) {
+            bail!("{} and {} would both build {}, use an --object-template with {{dir}} or {{}}", other, name, object);
+        }
+        objects.push((file, object));
+    }
+    Ok(objects)
+}
+
+/// Ninja fragment compiling each file once the objects of its dependencies are built
+pub fn ninja(output: &mut String, graph: &Graph, files: &[&File], compile_cmd: &str, object_template: &str,
+             output_name: &dyn Fn(&Path) -> Result<String>) -> Result<()> {
+    let objects: HashMap<_, _> = objects(files, object_template, output_name)?.into_iter()
+        .map(|(file, object)| (file, quote::ninja(&object)))
+        .collect();
+    writeln!(output, "rule sv_compile")?;
+    writeln!(output, "  command = {}", compile_cmd)?;
+    writeln!(output, "  description = Compiling $in")?;
+    for &file in files {
+        let mut implicit: Vec<_> = graph.deps[file].iter().filter_map(|dep| objects.get(dep).cloned()).collect();
+        implicit.sort_unstable();
+        implicit.extend(sorted_paths(file.includes.iter().map(|include| Ok(quote::ninja(&output_name(include)?))))?);
+        let implicit = if implicit.is_empty() { String::new() } else { format!(" | {}", implicit.join(" ")) };
+        writeln!(output, "\nbuild {}: sv_compile {}{}", objects[file], quote::ninja(&output_name(&file.name)?), implicit)?;
+    }
+    let all: Vec<_> = files.iter().map(|file| objects[file].as_str()).collect();
+    writeln!(output, "\nbuild all: phony {}", all.join(" "))?;
+    writeln!(output, "default all")?;
+    Ok(())
+}
+
+/// Vivado script reading the files in order, or adding them to the project with project
+pub fn vivado_tcl(output: &mut String, sources: &[Source], setup: &Setup, project: bool) -> Result<()> {
+    let tcl = |path: &str| quote::tcl(&path.replace('\\', "/"));
+    for Source { path, .. } in sources {
+        let command = match (project, language(path)) {
+            (true, _) => "add_files -norecurse",
+            (false, "vhdl") => "read_vhdl",
+            (false, "verilog") => "read_verilog",
+            (false, _) => "read_verilog -sv",
+        };
+        writeln!(output, "{} {}", command, tcl(path))?;
+    }
+    if !setup.incdirs.is_empty() {
+        let incdirs: Vec<_> = setup.incdirs.iter().map(|dir| tcl(dir)).collect();
+        writeln!(output, "set_property include_dirs {{{}}} [current_fileset]", incdirs.join(" "))?;
+    }
+    if !setup.defines.is_empty() {
+        let defines: Vec<_> = setup.defines.iter().map(|define| quote::tcl(define)).collect();
+        writeln!(output, "set_property verilog_define {{{}}} [current_fileset]", defines.join(" "))?;
+    }
+    Ok(())
+}
+
+/// Shell script calling vlog, or vcom for VHDL, for each run of files in the same library.
+/// With an arguments file, returns its contents and calls vlog once on it
+pub fn questa(output: &mut String, sources: &[Source], setup: &Setup, args_file: Option<&str>) -> Result<Option<String>> {
+    let mut options: Vec<_> = setup.incdirs.iter().map(|dir| format!("+incdir+{}", dir)).collect();
+    options.extend(setup.defines.iter().map(|define| format!("+define+{}", define)));
+    let runs: Vec<_> = runs(sources, |source| {
+        // -sv would also compile the Verilog files as SystemVerilog
+        let tool = match language(&source.path) {
+            "vhdl" => "vcom",
+            "verilog" => "vlog",
+            _ => "vlog -sv",
+        };
+        (tool, source.library.as_deref())
+    }).into_iter().map(|((tool, library), paths)| (tool, library, paths)).collect();
+
+    writeln!(output, "#!/bin/sh")?;
+    writeln!(output, "# Compiles the sources in dependency order")?;
+    writeln!(output, "set -e")?;
+    let mut libraries: Vec<_> = runs.iter().filter_map(|(_, library, _)| *library).collect();
+    libraries.sort_unstable();
+    libraries.dedup();
+    for library in libraries {
+        writeln!(output, "vlib {}", quote::shell(library))?;
+    }
+    if let Some(args_file) = args_file {
+        if runs.iter().any(|(tool, library, _)| *tool == "vcom" || *library != runs[0].1) {
+            bail!("--args-file only works if all the files are Verilog in the same library");
+        }
+        let mut arguments = String::new();
+        let paths = runs.iter().flat_map(|(_, _, paths)| paths.iter().copied());
+        for argument in options.iter().map(String::as_str).chain(paths) {
+            writeln!(arguments, "{}", Quote::Auto.filelist(argument))?;
+        }
+        let work = match runs.first() {
+            Some((_, Some(library), _)) => format!(" -work {}", quote::shell(library)),
+            _ => String::new(),
+        };
+        // Without -sv, vlog still compiles the .sv files as SystemVerilog from their extension
+        let tool = if runs.iter().all(|(tool, _, _)| *tool == "vlog -sv") { "vlog -sv" } else { "vlog" };
+        writeln!(output, "{}{} -f {}", tool, work, quote::shell(args_file))?;
+        return Ok(Some(arguments))
+    }
+    for (tool, library, paths) in runs {
+        write!(output, "{}", tool)?;
+        if let Some(library) = library {
+            write!(output, " -work {}", quote::shell(library))?;
+        }
+        // vcom has no include paths or defines
+        let options = options.iter().filter(|_| tool != "vcom").map(String::as_str);
+        for argument in options.chain(paths) {
+            write!(output, " \\\n    {}", quote::shell(argument))?;
+        }
+        writeln!(output)?;
+    }
+    Ok(None)
+}
+
+/// VCS arguments file, VHDL files are left out since vhdlan compiles them
+pub fn vcs_f(output: &mut String, sources: &[Source], setup: &Setup, quote: Quote, sverilog: bool) -> Result<()> {
+    if sverilog {
+        writeln!(output, "-sverilog")?;
+    }
+    for dir in &setup.incdirs {
+        writeln!(output, "{}", quote.filelist(&format!("+incdir+{}", dir)))?;
+    }
+    for define in &setup.defines {
+        writeln!(output, "{}", quote.filelist(&format!("+define+{}", define)))?;
+    }
+    for Source { path, .. } in sources {
+        if language(path) == "vhdl" {
+            warning!("output", "leaving VHDL file {} out of the VCS arguments, it needs vhdlan", path);
+            continue
+        }
+        writeln!(output, "{}", quote.filelist(path))?;
+    }
+    Ok(())
+}
+
+/// Xcelium arguments file, recording how it was generated
+pub fn xrun_f(output: &mut String, sources: &[Source], setup: &Setup, quote: Quote) -> Result<()> {
+    let invocation: Vec<_> = std::env::args().map(|arg| quote::shell(&arg)).collect();
+    writeln!(output, "// Generated by {}", invocation.join(" "))?;
+    for dir in &setup.incdirs {
+        writeln!(output, "-incdir {}", quote.filelist(dir))?;
+    }
+    for define in &setup.defines {
+        writeln!(output, "-define {}", quote.filelist(define))?;
+    }
+    // xrun goes by the extension, -sv is only worth it when every Verilog file is SystemVerilog
+    let mut verilog = sources.iter().map(|source| language(&source.path)).filter(|&language| language != "vhdl").peekable();
+    if verilog.peek().is_some() && verilog.all(|language| language == "systemverilog") {
+        writeln!(output, "-sv")?;
+    }
+    for (library, paths) in runs(sources, |source| source.library.as_deref()) {
+        if let Some(library) = library {
+            writeln!(output, "-makelib {}", quote.filelist(library))?;
+        }
+        for path in paths {
+            writeln!(output, "{}{}", if library.is_some() { "    " } else { "" }, quote.filelist(path))?;
+        }
+        if library.is_some() {
+            writeln!(output, "-endlib")?;
+        }
+    }
+    Ok(())
+}
+
+/// Verilator arguments file, optionally leaving the -y directories for Verilator to search
+pub fn verilator(output: &mut String, sources: &[Source], setup: &Setup, quote: Quote, keep_library_dirs: bool) -> Result<()> {
+    for dir in &setup.incdirs {
+        writeln!(output, "{}", quote.filelist(&format!("+incdir+{}", dir)))?;
+    }
+    for define in &setup.defines {
+        writeln!(output, "{}", quote.filelist(&format!("+define+{}", define)))?;
+    }
+    if let [top] = setup.tops.as_slice() {
+        writeln!(output, "--top-module {}", top)?;
+    }
+    if keep_library_dirs && !setup.library_dirs.is_empty() {
+        for dir in &setup.library_dirs {
+            writeln!(output, "-y {}", quote.filelist(dir))?;
+        }
+        let default_exts = ["v".to_owned(), "sv".to_owned()];
+        let exts = if setup.libext.is_empty() { &default_exts[..] } else { &setup.libext };
+        let exts: Vec<_> = exts.iter().map(|ext| format!(".{}", ext)).collect();
+        writeln!(output, "+libext+{}", exts.join("+"))?;
+    }
+    for source in sources {
+        if language(&source.path) == "vhdl" {
+            warning!("output", "leaving VHDL file {} out of the Verilator arguments", source.path);
+        } else if !(keep_library_dirs && source.searched) {
+            writeln!(output, "{}", quote.filelist(&source.path))?;
+        }
+    }
+    Ok(())
+}
+
+/// FuseSoC CAPI2 core with a single fileset, the headers are include files rather than ordered
+pub fn fusesoc(output: &mut String, sources: &[Source], headers: &[String], setup: &Setup, core_name: &str) -> Result<()> {
+    let mut parameters = Vec::new();
+    for define in &setup.defines {
+        let (name, value) = match define.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (define.as_str(), None),
+        };
+        if name.contains('(') {
+            warning!("output", "leaving macro {} out of the core, FuseSoC can't define macros with arguments", name);
+            continue
+        }
+        parameters.push((name, value));
+    }
+
+    writeln!(output, "CAPI=2:")?;
+    writeln!(output, "name: {}", quote::yaml(core_name))?;
+    writeln!(output, "\nfilesets:")?;
+    writeln!(output, "  rtl:")?;
+    writeln!(output, "    files:")?;
+    for Source { path, .. } in sources {
+        match language(path) {
+            "vhdl" => writeln!(output, "      - {}: {{file_type: vhdlSource}}", quote::yaml(path))?,
+            "verilog" => writeln!(output, "      - {}: {{file_type: verilogSource}}", quote::yaml(path))?,
+            _ => writeln!(output, "      - {}", quote::yaml(path))?,
+        }
+    }
+    for header in headers {
+        writeln!(output, "      - {}: {{is_include_file: true}}", quote::yaml(header))?;
+    }
+    writeln!(output, "    file_type: systemVerilogSource")?;
+    if !parameters.is_empty() {
+        writeln!(output, "\nparameters:")?;
+        for (name, value) in &parameters {
+            writeln!(output, "  {}:", quote::yaml(name))?;
+            // Defines without a value are flags
+            match value {
+                Some(value) => writeln!(output, "    datatype: str\n    default: {}", serde_json::to_string(value)?)?,
+                None => writeln!(output, "    datatype: bool\n    default: true")?,
+            }
+            writeln!(output, "    paramtype: vlogdefine")?;
+        }
+    }
+    writeln!(output, "\ntargets:")?;
+    writeln!(output, "  default:")?;
+    writeln!(output, "    filesets: [rtl]")?;
+    if !parameters.is_empty() {
+        let names: Vec<_> = parameters.iter().map(|(name, _)| quote::yaml(name)).collect();
+        writeln!(output, "    parameters: [{}]", names.join(", "))?;
+    }
+    if let [top] = setup.tops.as_slice() {
+        writeln!(output, "    toplevel: {}", quote::yaml(top))?;
+    }
+    Ok(())
+}
+
+/// Bazel targets, one per file in the BUILD file of its directory, and an all target depending on the roots
+pub fn bazel(output: &mut String, graph: &Graph, files: &[&File], rule: &str, workspace_root: &Path) -> Result<()> {
+    // Targets are named after the file, unless another file of the package has the same stem
+    let mut locations = HashMap::new();
+    let mut stems: HashMap<(String, String), usize> = HashMap::new();
+    for &file in files {
+        let path = canonical(&file.name);
+        let relative = path.strip_prefix(workspace_root).ok()
+            .with_context(|| format!("{} is outside of the workspace root {}", file.name.display(), workspace_root.display()))?;
+        let package = relative.parent().map(|dir| dir.to_string_lossy().replace('\\', "/")).unwrap_or_default();
+        let file_name = relative.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let stem = relative.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        // The characters Bazel allows in package and target names, which don't include spaces
+        if !package.chars().all(|c| c.is_ascii_alphanumeric() || "/-.@_".contains(c)) {
+            bail!("{} is in {}, which isn't a valid Bazel package name", file.name.display(), package);
+        }
+        if !file_name.chars().all(|c| c.is_ascii_alphanumeric() || "!%-@^_\"#$&'()*+,;<=>?[]{|}~/.".contains(c)) {
+            bail!("{} isn't a valid Bazel target name", file_name);
+        }
+        *stems.entry((package.clone(), stem.clone())).or_default() += 1;
+        locations.insert(file, (package, file_name, stem));
+    }
+    let targets: HashMap<&File, (&str, String, &str)> = locations.iter()
+        .map(|(&file, (package, file_name, stem))| {
+            let name = match stems[&(package.clone(), stem.clone())] {
+                1 => stem.clone(),
+                _ => file_name.replace('.', "_"),
+            };
+            (file, (package.as_str(), name, file_name.as_str()))
+        })
+        .collect();
+    let label = |from: &str, (package, name, _): &(&str, String, &str)| {
+        if from == *package { format!(":{}", name) } else { format!("//{}:{}", package, name) }
+    };
+
+    let mut packages: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    let used: HashSet<&File> = files.iter().flat_map(|file| &graph.deps[file]).copied().collect();
+    for &file in files {
+        let target = &targets[file];
+        let mut deps: Vec<_> = graph.deps[file].iter().filter_map(|dep| targets.get(dep)).map(|dep| label(target.0, dep)).collect();
+        deps.sort_unstable();
+        packages.entry(target.0).or_default().push(starlark_target(rule, &target.1, Some(target.2), &deps));
+    }
+    let mut roots: Vec<_> = files.iter().filter(|file| !used.contains(*file)).map(|file| label("", &targets[file])).collect();
+    roots.sort_unstable();
+
+    for rules in packages.values_mut() {
+        rules.sort_unstable();
+    }
+    packages.entry("").or_default().push(starlark_target(rule, "all", None, &roots));
+    for (i, (package, rules)) in packages.iter().enumerate() {
+        if i > 0 {
+            writeln!(output)?;
+        }
+        writeln!(output, "# //{}:BUILD.bazel", package)?;
+        for rule in rules {
+            write!(output, "\n{}", rule)?;
+        }
+    }
+    Ok(())
+}
+
+fn starlark_target(rule: &str, name: &str, src: Option<&str>, deps: &[String]) -> String {
+    let string = |s: &str| serde_json::to_string(s).unwrap();
+    let mut target = format!("{}(\n    name = {},\n", rule, string(name));
+    if let Some(src) = src {
+        target += &format!("    srcs = [{}],\n", string(src));
+    }
+    if !deps.is_empty() {
+        target += "    deps = [\n";
+        for dep in deps {
+            target += &format!("        {},\n", string(dep));
+        }
+        target += "    ],\n";
+    }
+    target + ")\n"
+}
+
+/// CMake variables with the files in order, the include paths and the defines.
+/// May also make the objects of the files depend on their headers
+pub fn cmake(output: &mut String, sources: &[Source], setup: &Setup, object_depends: Option<&[&File]>,
+             output_name: &dyn Fn(&Path) -> Result<String>) -> Result<()> {
+    let paths: Vec<_> = sources.iter().map(|source| quote::cmake(&source.path)).collect();
+    let incdirs: Vec<_> = setup.incdirs.iter().map(|dir| quote::cmake(dir)).collect();
+    // Backslashes in define values are not path separators
+    let defines: Vec<_> = setup.defines.iter().map(|define| quote::cmake_value(define)).collect();
+    for (variable, values) in [("SV_SOURCES_ORDERED", paths), ("SV_INCLUDE_DIRS", incdirs), ("SV_DEFINES", defines)] {
+        writeln!(output, "set({}", variable)?;
+        for value in values {
+            writeln!(output, "    {}", value)?;
+        }
+        writeln!(output, ")")?;
+    }
+    for &file in object_depends.unwrap_or_default().iter().filter(|file| !file.includes.is_empty()) {
+        let headers = sorted_paths(file.includes.iter().map(|include| output_name(include)))?;
+        writeln!(output, "set_source_files_properties({} PROPERTIES OBJECT_DEPENDS {})",
+                 quote::cmake(&output_name(&file.name)?), quote::cmake_list(&headers.iter().map(String::as_str).collect::<Vec<_>>()))?;
+    }
+    Ok(())
+}
+
+/// An hdlmake Manifest.py with the files in order, the include paths and the defines
+pub fn hdlmake(output: &mut String, sources: &[Source], setup: &Setup) -> Result<()> {
+    let paths: Vec<_> = sources.iter().map(|source| source.path.clone()).collect();
+    manifest(output, &paths, &setup.incdirs, &setup.defines, None)
+}
+
+/// A Manifest.py for each directory with sources, with paths relative to it.
+/// The directories of the files a directory depends on are its local modules
+pub fn hdlmake_per_dir(output: &mut String, graph: &Graph, files: &[&File], sources: &[Source], incdirs: &[PathBuf], defines: &[String],
+                       output_name: &dyn Fn(&Path) -> Result<String>) -> Result<()> {
+    let dir_of = |path: &Path| canonical(path).parent().map(Path::to_owned).unwrap_or_default();
+    // An include path that doesn't exist yet is still relative to the current directory
+    let absolute = |path: &Path| if path.exists() { canonical(path) } else { canonical(Path::new(".")).join(path) };
+    let mut manifests: BTreeMap<PathBuf, (String, Vec<String>, BTreeSet<String>)> = BTreeMap::new();
+    for source in sources {
+        let dir = dir_of(&source.name);
+        let path = relative_path(&canonical(&source.name), &dir).to_string_lossy().into_owned();
+        if let Some((_, paths, _)) = manifests.get_mut(&dir) {
+            paths.push(path);
+            continue
+        }
+        let parent = source.name.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+        manifests.insert(dir, (output_name(parent)?, vec![path], BTreeSet::new()));
+    }
+    for &file in files {
+        let dir = dir_of(&file.name);
+        let modules: Vec<_> = graph.deps[file].iter().map(|dep| dir_of(&dep.name)).filter(|dep_dir| *dep_dir != dir).collect();
+        if let Some((_, _, local)) = manifests.get_mut(&dir) {
+            local.extend(modules.iter().map(|dep_dir| relative_path(dep_dir, &dir).to_string_lossy().into_owned()));
+        }
+    }
+    for (i, (dir, (name, paths, local))) in manifests.iter().enumerate() {
+        if i > 0 {
+            writeln!(output)?;
+        }
+        writeln!(output, "# {}/Manifest.py", name)?;
+        let incdirs: Vec<_> = incdirs.iter().map(|incdir| relative_path(&absolute(incdir), dir).to_string_lossy().into_owned()).collect();
+        manifest(output, paths, &incdirs, defines, Some(&local.iter().cloned().collect::<Vec<_>>()))?;
+    }
+    Ok(())
+}
+
+fn manifest(output: &mut String, files: &[String], incdirs: &[String], defines: &[String], local: Option<&[String]>) -> Result<()> {
+    let list = |items: &[String]| match items {
+        [] => "[]".to_owned(),
+        _ => format!("[\n{}]", items.iter().map(|item| format!("    {},\n", serde_json::to_string(item).unwrap())).collect::<String>()),
+    };
+    writeln!(output, "files = {}", list(files))?;
+    writeln!(output, "include_dirs = {}", list(incdirs))?;
+    writeln!(output, "defines = {}", list(defines))?;
+    if let Some(local) = local.filter(|local| !local.is_empty()) {
+        writeln!(output, "modules = {{\"local\": {}}}", list(local))?;
+    }
+    Ok(())
+}
+
+/// Writes a JSON value as block style YAML, keeping the order of the keys
+pub fn yaml(output: &mut String, value: &serde_json::Value, indent: usize) -> Result<()> {
+    use serde_json::Value;
+    let pad = " ".repeat(indent);
+    match value {
+        Value::Object(map) if !map.is_empty() => for (key, value) in map {
+            write!(output, "{}{}:", pad, quote::yaml(key))?;
+            match value {
+                Value::Object(map) if !map.is_empty() => {
+                    writeln!(output)?;
+                    yaml(output, value, indent + 2)?;
+                }
+                Value::Array(items) if !items.is_empty() => {
+                    writeln!(output)?;
+                    yaml(output, value, indent)?;
+                }
+                _ => {
+                    write!(output, " ")?;
+                    yaml(output, value, 0)?;
+                }
+            }
+        },
+        Value::Array(items) if !items.is_empty() => for item in items {
+            // The item is written as if it were indented, then its first indent becomes the dash
+            let mut nested = String::new();
+            yaml(&mut nested, item, indent + 2)?;
+            write!(output, "{}- {}", pad, nested.trim_start_matches(' '))?;
+        },
+        Value::Object(_) => writeln!(output, "{{}}")?,
+        Value::Array(_) => writeln!(output, "[]")?,
+        Value::String(text) => writeln!(output, "{}", quote::yaml(text))?,
+        Value::Null => writeln!(output, "null")?,
+        _ => writeln!(output, "{}", value)?,
+    }
+    Ok(())
+}
+
+/// Splits the sources where the key changes, keeping them in order
+fn runs<'s, K: PartialEq>(sources: &'s [Source], key: impl Fn(&'s Source) -> K) -> Vec<(K, Vec<&'s str>)> {
+    let mut runs: Vec<(K, Vec<&str>)> = Vec::new();
+    for source in sources {
+        let source_key = key(source);
+        match runs.last_mut() {
+            Some((last_key, paths)) if *last_key == source_key => paths.push(&source.path),
+            _ => runs.push((source_key, vec![&source.path])),
+        }
+    }
+    runs
+}
+
+/// The --format json document, with every list sorted except the order
+#[derive(Serialize)]
+pub struct Report {
+    order: Vec<String>,
+    files: Vec<FileReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unused: Option<Vec<UnusedReport>>,
+    skipped: Vec<SkippedReport>,
+}
+
+#[derive(Serialize)]
+struct FileReport {
+    path: String,
+    /// Not parsed, like the VHDL files kept in place
+    passthrough: bool,
+    #[serde(flatten)]
+    parsed: Option<ParsedReport>,
+}
+
+#[derive(Serialize)]
+struct ParsedReport {
+    defines: Units,
+    uses: Uses,
+    dependencies: Vec<String>,
+    unresolved: Vec<String>,
+    includes: Vec<String>,
+}
+
+/// A file that no root needs, for --report-unused
+#[derive(Serialize)]
+struct UnusedReport {
+    path: String,
+    defines: Units,
+    defines_nothing: bool,
+}
+
+/// A source left out without parsing it, like those missing with --allow-missing-sources
+#[derive(Serialize)]
+struct SkippedReport {
+    path: String,
+    reason: &'static str,
+}
+
+#[derive(Serialize)]
+struct Units {
+    modules: Vec<String>,
+    interfaces: Vec<String>,
+    programs: Vec<String>,
+    checkers: Vec<String>,
+    primitives: Vec<String>,
+    configs: Vec<String>,
+    packages_and_classes: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Uses {
+    #[serde(flatten)]
+    units: Units,
+    symbols: Vec<String>,
+    macros: Vec<String>,
+}
+
+impl Report {
+    /// Describes the ordered files, the other files only count as definitions. The passthrough paths are only listed
+    pub fn new(graph: &Graph, files: &[&File], ordered: &[&File], order: Vec<String>, passthrough: Vec<String>,
+               output_name: &dyn Fn(&Path) -> Result<String>) -> Result<Report> {
+        let defined: HashSet<&String> = files.iter()
+            .flat_map(|&f| units_defined(f).chain(&f.packages_defined))
+            .collect();
+        let mut reports = Vec::new();
+        for &file in ordered {
+            let unresolved = units_used(file).chain(&file.packages_used).filter(|name| !defined.contains(name));
+            reports.push(FileReport {
+                path: output_name(&file.name)?,
+                passthrough: false,
+                parsed: Some(ParsedReport {
+                    defines: Units::defined_by(file),
+                    uses: Uses {
+                        units: Units {
+                            modules: sorted(&file.modules_used),
+                            interfaces: sorted(&file.interfaces_used),
+                            programs: sorted(&file.programs_used),
+                            checkers: sorted(&file.checkers_used),
+                            primitives: sorted(&file.udps_used),
+                            configs: sorted(&file.configs_used),
+                            packages_and_classes: sorted(&file.packages_used),
+                        },
+                        symbols: sorted(&file.symbols_used),
+                        macros: sorted(&file.macros_used),
+                    },
+                    dependencies: sorted_paths(graph.deps[file].iter().map(|dep| output_name(&dep.name)))?,
+                    unresolved: sorted(unresolved),
+                    includes: sorted_paths(file.includes.iter().map(|include| output_name(include)))?,
+                }),
+            });
+        }
+        reports.extend(passthrough.into_iter().map(|path| FileReport { path, passthrough: true, parsed: None }));
+        reports.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Report { order, files: reports, unused: None, skipped: Vec::new() })
+    }
+
+    /// Adds the files left out of the order, with what they define
+    pub fn add_unused(&mut self, unused: &[&File], output_name: &dyn Fn(&Path) -> Result<String>) -> Result<()> {
+        let mut reports = unused.iter()
+            .map(|&file| Ok(UnusedReport {
+                path: output_name(&file.name)?,
+                defines: Units::defined_by(file),
+                defines_nothing: units_defined(file).chain(&file.packages_defined).next().is_none(),
+            }))
+            .collect::<Result<Vec<_>>>()?;
+        reports.sort_by(|a, b| a.path.cmp(&b.path));
+        self.unused = Some(reports);
+        Ok(())
+    }
+
+    /// Adds the sources that don't exist, which weren't parsed, as listed when their path can't be resolved
+    pub fn add_missing(&mut self, missing: &[PathBuf], output_name: &dyn Fn(&Path) -> Result<String>) {
+        self.skipped.extend(missing.iter().map(|path| SkippedReport {
+            path: output_name(path).unwrap_or_else(|_| path.to_string_lossy().into_owned()),
+            reason: "missing",
+        }));
+        self.skipped.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+}
+
+impl Units {
+    fn defined_by(file: &File) -> Units {
+        Units {
+            modules: sorted(&file.modules_defined),
+            interfaces: sorted(&file.interfaces_defined),
+            programs: sorted(&file.programs_defined),
+            checkers: sorted(&file.checkers_defined),
+            primitives: sorted(&file.udps_defined),
+            configs: sorted(&file.configs_defined),
+            packages_and_classes: sorted(&file.packages_defined),
+        }
+    }
+}
+
+/// The --unresolved-report document, with an entry per name and kind of use, sorted
+#[derive(Serialize)]
+pub struct UnresolvedReport {
+    version: u32,
+    unresolved: Vec<UnresolvedName>,
+}
+
+#[derive(Serialize)]
+struct UnresolvedName {
+    name: String,
+    kind: &'static str,
+    /// Matched an --ignore-module or --blackbox-file pattern, so its uses were ignored
+    blackboxed: bool,
+    files: Vec<String>,
+    file_count: usize,
+    /// How many times each file references the name
+    references: Vec<References>,
+}
+
+#[derive(Serialize)]
+struct References {
+    file: String,
+    count: usize,
+}
+
+impl UnresolvedReport {
+    /// The names the ordered files use that the resolver found no file for, and those that were blackboxed
+    pub fn new(graph: &Graph, output_name: &dyn Fn(&Path) -> Result<String>) -> Result<UnresolvedReport> {
+        let ordered: HashSet<&File> = graph.order.iter().copied().collect();
+        let mut names: BTreeMap<(&str, &str, bool), BTreeMap<String, &File>> = BTreeMap::new();
+        for (file, reason) in graph.misses.iter().filter(|(file, _)| ordered.contains(file)) {
+            names.entry((&reason.name, reason.kind, false)).or_default().insert(output_name(&file.name)?, file);
+        }
+        for &file in &graph.order {
+            for (name, kind) in &file.ignored_uses {
+                names.entry((name, kind, true)).or_default().insert(output_name(&file.name)?, file);
+            }
+        }
+        let unresolved = names.into_iter()
+            .map(|((name, kind, blackboxed), files)| UnresolvedName {
+                name: name.to_owned(),
+                kind,
+                blackboxed,
+                file_count: files.len(),
+                // A use only in a branch of --all-ifdef-branches isn't in the counted parse
+                references: files.iter().map(|(path, file)| References { file: path.clone(), count: file.count_identifier(name).max(1) }).collect(),
+                files: files.into_keys().collect(),
+            })
+            .collect();
+        Ok(UnresolvedReport { version: 1, unresolved })
+    }
+}
+
+fn sorted<'a>(names: impl IntoIterator<Item=&'a String>) -> Vec<String> {
+    let mut names: Vec<_> = names.into_iter().cloned().collect();
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+fn sorted_paths(paths: impl Iterator<Item=Result<String>>) -> Result<Vec<String>> {
+    let mut paths = paths.collect::<Result<Vec<_>>>()?;
+    paths.sort_unstable();
+    Ok(paths)
+}
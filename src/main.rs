@@ -1,124 +1,2289 @@
 #![recursion_limit="256"]
 
-use clap::{App, arg};
+use clap::{Arg, ArgMatches, Command, arg};
 use rayon::prelude::*;
-use std::collections::{HashMap, HashSet};
-use std::path::Path;
-use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, bail, Context, Result};
+use glob::Pattern;
+use serde_json::json;
+use sv_parser::{Define, DefineText, Defines};
 
+#[macro_use]
+mod diagnostics;
+mod config;
 mod file;
-use file::File;
+mod filelist;
+mod formats;
+mod quote;
+use config::Config;
+use diagnostics::{Diagnostic, Format, Parsing};
+use file::{ConfigRules, File, ParseOptions, Standard};
+use filelist::{Inputs, LibraryMap};
+use quote::Quote;
 
-fn main() -> Result<()> {
-    let args = App::new("SV Auto Order")
+/// What to do when several files define the same module or package
+#[derive(Clone, Copy)]
+enum OnDuplicate {
+    Error,
+    Warn,
+    First,
+    Last,
+}
+
+/// What to do with the names used but defined by no file
+#[derive(Clone, Copy, PartialEq)]
+enum OnUnresolved {
+    Ignore,
+    Warn,
+    Error,
+}
+
+/// Picks the definition to use for a name defined by several files
+#[derive(Clone)]
+struct DuplicatePolicy {
+    /// Files matching an earlier pattern win over later patterns and files matching none
+    prefer_files: Vec<Pattern>,
+    on_duplicate: OnDuplicate,
+}
+
+impl DuplicatePolicy {
+    /// Same choice of definition, without the warnings
+    fn quiet(&self) -> Self {
+        let on_duplicate = match self.on_duplicate {
+            OnDuplicate::Warn => OnDuplicate::First,
+            policy => policy,
+        };
+        DuplicatePolicy { on_duplicate, ..self.clone() }
+    }
+
+    fn preference(&self, file: &File) -> usize {
+        self.prefer_files.iter().position(|p| p.matches_path(&file.name)).unwrap_or(self.prefer_files.len())
+    }
+}
+
+// Bounds --propagate-defines when re-parsing keeps changing the order
+const MAX_DEFINE_PASSES: usize = 8;
+// More automatic include paths than this are probably a mistake
+const MAX_AUTO_INCDIRS: usize = 200;
+
+/// Options shared by all commands, they may come before or after the command name
+fn shared_args() -> Vec<Arg<'static>> {
+    let args = vec![
+        arg!(--config <file> "Read settings from this file instead of the closest sv_auto_order.toml").required(false).allow_invalid_utf8(true),
+        arg!(--"no-config" "Don't read settings from a sv_auto_order.toml")
+            .conflicts_with("config"),
+        arg!(-v --verbose "Print progress on stderr, -vv also prints every dependency. Unlike in filelists, -v isn't a library file here")
+            .multiple_occurrences(true),
+        arg!(-q --quiet "Don't print warnings"),
+        arg!(--"error-format" <format> "Print warnings and errors as text, JSON objects or GitHub Actions annotations")
+            .required(false).possible_values(["text", "json", "github"]).default_value("text"),
+        arg!(-j --jobs <count> "Number of threads parsing files, 1 parses them in order. Defaults to $SV_AUTO_ORDER_JOBS, or the number of CPUs").required(false),
+        arg!(--passthrough <extensions> "Comma separated extensions of files to output without parsing them, like VHDL")
+            .required(false).default_value("vhd,vhdl"),
+        arg!(--"keep-going" "Parse every file and report all the errors, instead of stopping at the first one"),
+        arg!(--"partial-output" "With --keep-going, still output the results for the files that parsed")
+            .requires("keep-going"),
+        arg!(-o --output <file> "Write the results to a file once complete instead of stdout, - for stdout").required(false).allow_invalid_utf8(true),
+        arg!(-a --absolute "Output absolute paths"),
+        arg!(--"relative-to" <dir> "Output paths relative to a directory").required(false).allow_invalid_utf8(true)
+            .conflicts_with("absolute"),
+        arg!(--"macro-deps" "Order files defining macros before the files using them"),
+        arg!(--"propagate-defines" "Re-parse files with the macros defined by the files ordered before them"),
+        arg!(--"all-ifdef-branches" "Also parse each file with every macro its conditionals test flipped, to see the dependencies of all branches"),
+        arg!(--"on-duplicate" <policy> "What to do when several files define the same name")
+            .required(false).possible_values(["error", "warn", "first", "last"]).default_value("last"),
+        arg!(--unresolved <policy> "What to do with the modules, packages and classes no source defines")
+            .required(false).possible_values(["ignore", "warn", "error"]).default_value("ignore"),
+        arg!(--strict "Fail if a module, package or class isn't defined by any source, same as --unresolved error"),
+        arg!(--top <module> "Only output the files needed to compile this module").required(false),
+        arg!(--"tops-from" <file> "Read tops from a file, one module per line").required(false),
+        arg!(--"auto-top" "Use every module never instantiated as a top, instead of every file nothing depends on"),
+        arg!(--"ignore-module" <pattern> "Ignore the uses of a cell no source defines, like sky130_*").required(false).multiple_occurrences(true),
+        arg!(--"blackbox-file" <file> "Read cells to ignore from a file, one name or pattern per line").required(false),
+        arg!(--"prefer-file" <pattern> "Prefer definitions from files matching this pattern over other files defining the same name. \
+            The files that only define names another file wins are left out, with or without --top")
+            .required(false).multiple_occurrences(true),
+        arg!(--uvm "Assume UVM is provided precompiled, ignore uvm_pkg and stub out the UVM macros"),
+        arg!(--std <standard> "Language standard of all sources, instead of v2001 for .v files and sv2017 otherwise")
+            .required(false).possible_values(["sv2017", "sv2012", "v2001", "v95"]),
+        arg!(--"macro-instances" "Report the instantiations produced by macro expansion"),
+        arg!(--"hier-deps" "Order files after the modules named at the root of their hierarchical references and defparams"),
+        arg!(--"timescale-first" "Output files setting a `timescale before the others when dependencies allow it"),
+        arg!(--lint "Warn about constructs that limit the accuracy of the order"),
+        arg!(--"lint-timescale" "Warn about the files with no `timescale, which inherit the one of the files compiled before them"),
+        arg!(--"no-builtin-filter" "Don't ignore uses of modules named like built-in gate primitives"),
+        arg!(--"deep-resolve" "Match unscoped identifiers against the symbols declared by each package (slow). \
+            The packages a file imports come first, --on-duplicate picks between the others"),
+        arg!(--"compilation-unit" "Resolve typedefs, parameters and functions declared outside of any module or package (heuristic)"),
+        arg!(defines: -D --define <value> "Define a macro, as NAME, NAME=VALUE or NAME(ARGS)=VALUE").required(false).multiple_occurrences(true),
+        arg!(--"defines-from" <file> "Read defines from a file of +define+ plusargs or `define lines, -D overrides them").required(false).allow_invalid_utf8(true),
+        arg!(filelists: -f --filelist <file> "Read sources, +incdir+ and +define+ from a filelist").required(false).multiple_occurrences(true).allow_invalid_utf8(true),
+        arg!(--"allow-unset-env" "Leave unset environment variables in filelists as they are instead of failing"),
+        arg!(relative_filelists: -F <file> "Read a filelist whose paths are relative to its own directory").required(false).multiple_occurrences(true).allow_invalid_utf8(true),
+        arg!(--scan <dir> "Add the sources found recursively in a directory").required(false).multiple_occurrences(true).allow_invalid_utf8(true),
+        arg!(--ext <extensions> "Comma separated extensions of the sources found by --scan").required(false).default_value("sv,v"),
+        arg!(--exclude <pattern> "Don't use the sources matching this pattern, even if listed or found by --scan").required(false).multiple_occurrences(true),
+        arg!(--glob "Expand ? and [...] in sources as glob patterns, patterns with a * always are"),
+        arg!(--"allow-empty-glob" "Don't fail on glob patterns matching no file"),
+        arg!(--"library-file" <file> "Parse a library file, but only output it if another file uses it. This is -v in filelists, on the command line -v is --verbose").required(false).multiple_occurrences(true).allow_invalid_utf8(true),
+        arg!(--libmap <file> "Put sources in the libraries of a library map, so config blocks choose between definitions. The configs not used by others become the tops").required(false).allow_invalid_utf8(true),
+        arg!(library_dirs: -y <dir> "Search a directory for the modules no source defines, in files named after them with a +libext+ extension, v or sv by default").required(false).multiple_occurrences(true).allow_invalid_utf8(true),
+        arg!(--"allow-missing-sources" "Skip the sources that don't exist with a warning, instead of failing"),
+        arg!(--"keep-included-sources" "Order the sources that other sources `include, instead of leaving them out"),
+        arg!(--"compile-headers" "Order the headers passed as sources like other files, instead of only taking their macros"),
+        arg!(--"skip-includes" "Don't follow `include directives, faster when headers declare nothing the order depends on"),
+        arg!(--"strict-parse" "Fail on syntax errors, instead of ignoring the rest of the file after one"),
+        arg!(--"no-parent-incdir" "Only search includes in the include paths, not in the directory of the including file"),
+        arg!(--"auto-incdir" "Add the directories of all sources to the include paths, and those with headers under --scan roots"),
+        arg!(include_paths: -i --"include-path" <value> "Add a directory to the include paths").required(false).multiple_occurrences(true).allow_invalid_utf8(true),
+    ];
+    args.into_iter().map(|arg| arg.global(true)).collect()
+}
+
+/// Options of the order command, which is also the default without a command name
+fn order_args() -> Vec<Arg<'static>> {
+    vec![
+        arg!(--"passthrough-position" <position> "Where to put the files passed through, near their input position or all at the front or back")
+            .required(false).possible_values(["input", "front", "back"]).default_value("input"),
+        arg!(--stats "Print a summary of the files and dependencies on stderr"),
+        arg!(--"only-new" <filelist> "Only output the files missing from an existing filelist, assuming it is compiled first").required(false).allow_invalid_utf8(true),
+        arg!(--"stats-json" "Print the --stats summary as JSON"),
+        arg!(--"list-modules" "List the modules, interfaces, programs, checkers, primitives and configs defined by each file instead of ordering"),
+        arg!(--"list-packages" "List the packages and classes defined by each file instead of ordering"),
+        arg!(--"list-undefined" "List the names used but not defined by any file, with the files using them, instead of ordering"),
+        arg!(--"print-defines" "Print the macros defined at the end of each file instead of ordering"),
+        arg!(--format <format> "Output format, dot is a Graphviz graph of the dependencies").required(false).possible_values(["text", "json", "dot", "make", "ninja", "vivado-tcl", "questa", "vcs-f", "xrun-f", "verilator", "fusesoc", "bazel", "cmake", "hdlmake", "mermaid", "csv", "yaml", "groups", "groups-json"]).default_value("text")
+            .long_help(JSON_FORMAT_HELP),
+        arg!(--"unresolved-report" <file> "Also write the names no file defines to a JSON file, with the files using them and how often").required(false).allow_invalid_utf8(true),
+        arg!(--"report-unused" "List the files no top needs, with what they define, on stderr or under unused in the json and yaml report"),
+        arg!(--explain <target> "Explain why a file, or the file defining a name, is where it is in the order, instead of ordering").required(false),
+        arg!(--"edge-labels" "Label the edges of the dependency graph with the names that cause them"),
+        arg!(--"max-nodes" <count> "Summarize what files depend on past this many files in the --format mermaid diagram").required(false),
+        arg!(--"object-template" <string> "Path of the target built from each file by --format make and ninja, with the placeholders of --template. Files with the same stem need {dir}")
+            .required(false).default_value("build/{stem}.o"),
+        arg!(--"vivado-mode" <mode> "Whether --format vivado-tcl reads the files into a non-project flow or adds them to a project")
+            .required(false).possible_values(["non-project", "project"]).default_value("non-project"),
+        arg!(--"args-file" <file> "Write the arguments of --format questa to a file, that a single vlog call reads with -f").required(false),
+        arg!(--sverilog "Start the --format vcs-f file with -sverilog"),
+        arg!(--"keep-library-dirs" "With --format verilator, pass on the -y directories instead of the files found in them"),
+        arg!(--"core-name" <vlnv> "Name of the --format fusesoc core, as vendor:library:name:version").required(false),
+        arg!(--"bazel-rule" <rule> "Rule of the --format bazel targets").required(false).default_value("sv_library"),
+        arg!(--"workspace-root" <dir> "Root of the Bazel workspace, that --format bazel labels are relative to").required(false).default_value(".").allow_invalid_utf8(true),
+        arg!(--"object-depends" "With --format cmake, set the OBJECT_DEPENDS of each file to the headers it includes"),
+        arg!(--"manifest-per-dir" "With --format hdlmake, write a manifest for each directory, using the others as local modules"),
+        arg!(--"compile-cmd" <command> "Command of the --format ninja rule compiling a file, like 'vlog $in && touch $out'").required(false),
+        arg!(--separator <string> "Separate the paths with a string instead of newlines").required(false)
+            .conflicts_with_all(&["null", "space"]),
+        arg!(--template <string> "Format each path with a template, where {} is the path, {stem} the file name without extension and {dir} its directory").required(false),
+        arg!(--quote <style> "Quote the paths with special characters in the style of the output, or in a given style. auto leaves the plain list as is")
+            .required(false).possible_values(["auto", "always", "never", "shell", "tcl"]).default_value("auto"),
+        arg!(null: -'0' --null "Terminate each path with a NUL character, as for xargs -0")
+            .conflicts_with("space"),
+        arg!(--space "Follow each path with a space, the old output format"),
+        arg!(--"emit-includes" "Print the headers included by each source file"),
+    ]
+}
+
+fn sources_arg() -> Arg<'static> {
+    arg!([sources] "The source files, and any +define+ or +incdir+ plusargs. - reads source paths from stdin").multiple_values(true).allow_invalid_utf8(true)
+}
+
+fn cli() -> Command<'static> {
+    Command::new("SV Auto Order")
         .about("Detect compilation order for SystemVerilog files")
-        .arg(arg!(-v --verbose "Print more details")
+        .args(shared_args())
+        .args(order_args())
+        .arg(sources_arg())
+        .subcommand(Command::new("order")
+            .about("Print the sources in compilation order, the default without a command")
+            .args(order_args())
+            .arg(sources_arg())
         )
-        .arg(arg!(-a --absolute "Output absolute paths")
+        .subcommand(Command::new("graph")
+            .about("Print the dependency graph of the sources")
+            .arg(arg!(--format <format> "Output format, a Graphviz graph or the report of the order command --format json").required(false).possible_values(["dot", "json"]).default_value("dot"))
+            .arg(arg!(--"edge-labels" "Label the edges with the names that cause them"))
+            .arg(sources_arg())
         )
-        .arg(arg!(include_paths: -i --"include-path" [value] "Add a directory to the include paths").multiple_occurrences(true)
+        .subcommand(Command::new("query")
+            .about("Find the files defining a name, or the files a file or name depends on or is used by")
+            .arg(arg!(<query> "What to look for").possible_values(["find-module", "deps-of", "users-of"]))
+            .arg(arg!(<target> "A module, package or class name, or for deps-of and users-of a source file"))
+            .arg(sources_arg())
         )
-        .arg(
-            arg!(<sources> "The source files").multiple_values(true)
+        .subcommand(Command::new("check")
+            .about("Check that the files of a filelist are in a valid compilation order")
+            .arg(arg!(<order> "The filelist to check, its files are the sources unless others are given").allow_invalid_utf8(true))
+            .arg(sources_arg())
         )
-        .get_matches();
+}
+
+fn main() -> Result<()> {
+    let matches = cli().get_matches();
+    let (command, args) = matches.subcommand().unwrap_or(("order", &matches));
+    let format = match args.value_of("error-format").unwrap() {
+        "json" => Format::Json,
+        "github" => Format::Github,
+        _ => Format::Text,
+    };
+    diagnostics::configure(format, args.is_present("quiet"));
+    match run(command, args) {
+        Err(err) if format != Format::Text => {
+            let category = if err.downcast_ref::<Parsing>().is_some() { "parse" } else { "fatal" };
+            Diagnostic::from_error(category, &err).emit();
+            std::process::exit(1)
+        }
+        result => result,
+    }
+}
 
-    let verbose = args.is_present("verbose");
+fn run(command: &str, args: &ArgMatches) -> Result<()> {
+    let jobs = args.value_of("jobs").map(str::to_owned).or_else(|| std::env::var("SV_AUTO_ORDER_JOBS").ok());
+    if let Some(jobs) = jobs {
+        let jobs = jobs.parse().with_context(|| format!("Invalid job count {}", jobs))?;
+        rayon::ThreadPoolBuilder::new().num_threads(jobs).build_global()?;
+    }
+    // The filelist to check gives the sources, unless others are listed
+    let fallback_filelist = match command {
+        "check" => args.value_of_os("order").map(Path::new),
+        _ => None,
+    };
+    let mut project = Project::load(args, fallback_filelist)?;
+    let settings = match command {
+        "order" => Some(OrderSettings::new(args, &project)?),
+        _ => None,
+    };
     let absolute = args.is_present("absolute");
-    let incdirs: Vec<_> = args.values_of_os("include_paths").unwrap_or_default().map(Path::new).collect();
-    let filepaths: Vec<_> = args.values_of_os("sources").unwrap().collect();
-    let files = filepaths.into_par_iter()
-        .inspect(|f| if verbose { println!("Parsing {}", f.to_string_lossy()) } )
-        .map(Path::new)
-        .map(|p| File::new(p, &incdirs).with_context(|| format!("While parsing {}", p.display())))
+    let relative_to = args.value_of_os("relative-to").map(Path::new)
+        .or(project.relative_to.as_deref().filter(|_| !absolute))
+        .map(|dir| std::fs::canonicalize(dir).with_context(|| format!("Invalid --relative-to directory {}", dir.display())))
+        .transpose()?;
+
+    let parsed = project.parse()?;
+    let files = project.select(&parsed);
+
+    let output_name = |path: &Path| -> Result<String> {
+        Ok(if let Some(base) = &relative_to {
+            relative_path(&std::fs::canonicalize(path)?, base).to_string_lossy().into_owned()
+        } else if absolute {
+            std::fs::canonicalize(path)?.to_string_lossy().into_owned()
+        } else {
+            path.to_string_lossy().into_owned()
+        })
+    };
+    let mut undefined = 0;
+    let output = match command {
+        "graph" => graph_command(args, &project, &files, &output_name)?,
+        "query" => query_command(args, &project, &files, &output_name)?,
+        "check" => check_command(args, &project, &files, &output_name)?,
+        _ => {
+            let (output, listed_undefined) = order_command(settings.as_ref().unwrap(), &project, &parsed, &files, &output_name)?;
+            undefined = listed_undefined;
+            output
+        }
+    };
+
+    let failures = &parsed.failures;
+    if failures.is_empty() || args.is_present("partial-output") {
+        if !failures.is_empty() {
+            warning!("output", "the output only has the files that parsed, it may be wrong");
+        }
+        // The --format csv files are already written
+        let csv_prefix = settings.as_ref().and_then(|settings| settings.csv_prefix.as_ref());
+        match args.value_of_os("output").map(Path::new).filter(|p| *p != Path::new("-") && csv_prefix.is_none()) {
+            Some(path) => {
+                if project.verbose {
+                    eprintln!("Writing the output to {}", path.display());
+                }
+                write_atomic(path, &output)?;
+            }
+            None => print!("{}", output),
+        }
+    }
+    if !failures.is_empty() {
+        bail!("{} of {} files failed to parse", failures.len(), parsed.files.len() + failures.len());
+    }
+    if !project.missing_sources.is_empty() && args.is_present("strict") {
+        bail!("{} sources don't exist", project.missing_sources.len());
+    }
+    if undefined > 0 && project.on_unresolved == OnUnresolved::Error {
+        bail!("{} names are not defined by any source", undefined);
+    }
+    Ok(())
+}
+
+/// The sources to parse and how, from the command line and the config file
+struct Project {
+    verbosity: u64,
+    verbose: bool,
+    lint: bool,
+    lint_timescale: bool,
+    options: ParseOptions,
+    duplicates: DuplicatePolicy,
+    on_unresolved: OnUnresolved,
+    tops: Vec<String>,
+    auto_top: bool,
+    keep_going: bool,
+    keep_included: bool,
+    /// Patterns of the cells whose uses are ignored
+    ignored: Vec<Pattern>,
+    inputs: Inputs,
+    /// Files in other languages, with their position in the sources
+    passthrough: Vec<(usize, PathBuf)>,
+    missing_sources: Vec<PathBuf>,
+    libmap: Option<LibraryMap>,
+    defines: Defines,
+    /// The defines as given, sorted, for the scripts that pass them on to the tools
+    define_args: Vec<String>,
+    /// Output settings from the config, the command line has precedence
+    separator: Option<String>,
+    relative_to: Option<PathBuf>,
+}
+
+/// The sources that parsed, and with --keep-going the errors of those that didn't
+struct Parsed {
+    files: Vec<(PathBuf, Defines, File)>,
+    failures: Vec<(PathBuf, anyhow::Error)>,
+    time: std::time::Duration,
+}
+
+impl Project {
+    fn load(args: &ArgMatches, fallback_filelist: Option<&Path>) -> Result<Project> {
+        let verbosity = args.occurrences_of("verbose");
+        let verbose = verbosity > 0;
+        let config_path = match args.value_of_os("config") {
+            _ if args.is_present("no-config") => None,
+            Some(path) => Some(PathBuf::from(path)),
+            None => Config::find(),
+        };
+        let config = match &config_path {
+            Some(path) => {
+                if verbose {
+                    eprintln!("Using config {}", path.display());
+                }
+                Config::load(path)?
+            }
+            None => Config::default(),
+        };
+        let options = ParseOptions {
+            builtin_filter: !args.is_present("no-builtin-filter"),
+            deep_resolve: args.is_present("deep-resolve"),
+            compilation_unit: args.is_present("compilation-unit"),
+            macro_deps: args.is_present("macro-deps"),
+            propagate_defines: args.is_present("propagate-defines"),
+            all_ifdef_branches: args.is_present("all-ifdef-branches"),
+            uvm: args.is_present("uvm"),
+            macro_instances: args.is_present("macro-instances"),
+            hier_deps: args.is_present("hier-deps"),
+            timescale_first: args.is_present("timescale-first"),
+            parent_incdir: !args.is_present("no-parent-incdir"),
+            skip_includes: args.is_present("skip-includes"),
+            compile_headers: args.is_present("compile-headers"),
+            strict_parse: args.is_present("strict-parse"),
+            std: args.value_of("std").map(|std| match std {
+                "sv2012" => Standard::Sv2012,
+                "v2001" => Standard::V2001,
+                "v95" => Standard::V95,
+                _ => Standard::Sv2017,
+            }),
+        };
+        let on_duplicate = match args.value_of("on-duplicate").unwrap() {
+            "error" => OnDuplicate::Error,
+            "warn" => OnDuplicate::Warn,
+            "first" => OnDuplicate::First,
+            _ => OnDuplicate::Last,
+        };
+        let on_unresolved = match args.value_of("unresolved").unwrap() {
+            _ if args.is_present("strict") => OnUnresolved::Error,
+            "error" => OnUnresolved::Error,
+            "warn" => OnUnresolved::Warn,
+            _ => OnUnresolved::Ignore,
+        };
+        let prefer_files = args.values_of("prefer-file").unwrap_or_default()
+            .map(|p| Pattern::new(p).with_context(|| format!("Invalid --prefer-file pattern {}", p)))
+            .collect::<Result<_>>()?;
+        let duplicates = DuplicatePolicy { prefer_files, on_duplicate };
+        let mut tops: Vec<String> = args.value_of("top").map(str::to_owned).into_iter().collect();
+        if let Some(path) = args.value_of("tops-from") {
+            let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read tops from {}", path))?;
+            tops.extend(text.lines().map(|line| filelist::strip_comment(line).trim()).filter(|line| !line.is_empty()).map(str::to_owned));
+        }
+        let mut ignored_names: Vec<String> = args.values_of("ignore-module").unwrap_or_default().map(str::to_owned).collect();
+        if let Some(path) = args.value_of("blackbox-file") {
+            let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read blackbox cells from {}", path))?;
+            ignored_names.extend(text.lines().map(|line| filelist::strip_comment(line).trim()).filter(|line| !line.is_empty()).map(str::to_owned));
+        }
+        let ignored = ignored_names.iter()
+            .map(|p| Pattern::new(p).with_context(|| format!("Invalid --ignore-module pattern {}", p)))
+            .collect::<Result<Vec<_>>>()?;
+        let mut inputs = Inputs {
+            glob: args.is_present("glob"),
+            allow_empty_glob: args.is_present("allow-empty-glob"),
+            allow_unset_env: args.is_present("allow-unset-env"),
+            ..Inputs::default()
+        };
+        inputs.library_files.extend(args.values_of_os("library-file").unwrap_or_default().map(PathBuf::from));
+        inputs.library_dirs.extend(args.values_of_os("library_dirs").unwrap_or_default().map(PathBuf::from));
+        inputs.library_dirs.extend(config.library_dirs);
+        inputs.incdirs.extend(args.values_of_os("include_paths").unwrap_or_default().map(PathBuf::from));
+        inputs.incdirs.extend(config.include_paths);
+        // Later defines replace earlier ones, so the command line wins over the config
+        inputs.defines.extend(config.defines);
+        if let Some(path) = args.value_of_os("defines-from") {
+            inputs.defines.extend(read_defines_file(Path::new(path))?);
+        }
+        inputs.defines.extend(args.values_of("defines").unwrap_or_default().map(str::to_owned));
+        let extensions: Vec<_> = args.value_of("ext").unwrap().split(',').map(|e| e.trim_start_matches('.')).collect();
+        // Sources, filelists and scanned directories are taken in command line order, which breaks ties in the output order
+        let mut listed = Vec::new();
+        for name in ["sources", "filelists", "relative_filelists", "scan"] {
+            let indices = args.indices_of(name).unwrap_or_default();
+            listed.extend(indices.zip(args.values_of_os(name).unwrap_or_default()).map(|(i, arg)| (i, name, arg)));
+        }
+        listed.sort_by_key(|&(i, _, _)| i);
+        if listed.is_empty() {
+            listed.extend(fallback_filelist.map(|p| (0, "filelists", p.as_os_str())));
+        }
+        if listed.is_empty() {
+            listed.extend(config.sources.iter().map(|p| (0, "sources", p.as_os_str())));
+            listed.extend(config.scan.iter().map(|p| (0, "scan", p.as_os_str())));
+        }
+        for (_, name, arg) in listed {
+            match name {
+                "filelists" => inputs.add_filelist(Path::new(arg), false)?,
+                "relative_filelists" => inputs.add_filelist(Path::new(arg), true)?,
+                "scan" => {
+                    let found = inputs.add_scan(Path::new(arg), &extensions);
+                    if verbose {
+                        eprintln!("Found {} sources in {}", found, arg.to_string_lossy());
+                    }
+                }
+                _ if arg == "-" => inputs.add_stdin()?,
+                _ => inputs.add_arg(arg)?,
+            }
+        }
+        let excludes = args.values_of("exclude").unwrap_or_default()
+            .chain(config.exclude.iter().map(String::as_str))
+            .map(|p| Pattern::new(p).with_context(|| format!("Invalid --exclude pattern {}", p)))
+            .collect::<Result<Vec<_>>>()?;
+        let excluded = inputs.exclude(&excludes);
+        if verbose && excluded > 0 {
+            eprintln!("Excluded {} sources", excluded);
+        }
+        let duplicate_sources = inputs.dedupe();
+        if verbose {
+            for duplicate in duplicate_sources {
+                eprintln!("Ignoring {}, listed more than once", duplicate.display());
+            }
+        }
+        let missing_sources = inputs.take_missing();
+        if !missing_sources.is_empty() {
+            let list: Vec<_> = missing_sources.iter().map(|p| format!("\n  {}", p.display())).collect();
+            if !args.is_present("allow-missing-sources") {
+                bail!("{} sources don't exist:{}", missing_sources.len(), list.concat());
+            }
+            warning!("input", "skipping {} sources that don't exist:{}", missing_sources.len(), list.concat());
+        }
+        if inputs.sources.is_empty() {
+            bail!("No source files given");
+        }
+        // Files in other languages are output as they are, next to where they were listed
+        let passthrough_exts: Vec<_> = args.value_of("passthrough").unwrap().split(',')
+            .map(|e| e.trim_start_matches('.'))
+            .filter(|e| !e.is_empty())
+            .collect();
+        let mut passthrough = Vec::new();
+        for (i, source) in std::mem::take(&mut inputs.sources).into_iter().enumerate() {
+            if source.extension().and_then(|x| x.to_str()).is_some_and(|x| passthrough_exts.contains(&x)) {
+                if verbose {
+                    eprintln!("Passing through {}", source.display());
+                }
+                passthrough.push((i, source));
+            } else {
+                inputs.sources.push(source);
+            }
+        }
+        if args.is_present("auto-incdir") {
+            let auto_incdirs = inputs.auto_incdirs();
+            if auto_incdirs.len() > MAX_AUTO_INCDIRS {
+                warning!("input", "--auto-incdir found {} directories, includes may be slow to resolve or pick the wrong header", auto_incdirs.len());
+            }
+            if verbose {
+                let dirs: Vec<_> = auto_incdirs.iter().map(|d| d.to_string_lossy()).collect();
+                eprintln!("Automatic include paths: {}", dirs.join(", "));
+            }
+            inputs.incdirs.extend(auto_incdirs);
+        }
+        let libmap = args.value_of_os("libmap").map(|path| LibraryMap::load(Path::new(path))).transpose()?;
+        let mut define_args = inputs.defines.clone();
+        let defines = define_args.iter()
+            .map(|d| parse_define(d))
+            .collect::<Result<Defines>>()?;
+        // Only keep the definitions that won
+        let mut seen = HashSet::new();
+        define_args.reverse();
+        define_args.retain(|d| seen.insert(d.split(['(', '=']).next().unwrap().to_owned()));
+        define_args.sort_unstable();
+        if verbose && !defines.is_empty() {
+            eprintln!("Defines: {}", define_args.join(", "));
+        }
+
+        Ok(Project {
+            verbosity,
+            verbose,
+            lint: args.is_present("lint"),
+            lint_timescale: args.is_present("lint-timescale"),
+            options,
+            duplicates,
+            on_unresolved,
+            tops,
+            auto_top: args.is_present("auto-top"),
+            keep_going: args.is_present("keep-going"),
+            keep_included: args.is_present("keep-included-sources"),
+            ignored,
+            inputs,
+            passthrough,
+            missing_sources,
+            libmap,
+            defines,
+            define_args,
+            separator: config.separator,
+            relative_to: config.relative_to,
+        })
+    }
+
+    fn parse_file(&self, path: &Path, defines: &Defines) -> Result<File> {
+        let incdirs: Vec<_> = self.inputs.incdirs.iter().map(PathBuf::as_path).collect();
+        let mut file = File::new(path, &incdirs, defines, &self.options).with_context(|| Parsing(path.to_owned()))?;
+        file.library = self.inputs.library_files.iter().any(|l| l == path);
+        file.work_library = self.libmap.as_ref().map(|map| map.library_of(path));
+        file.ignore_uses(&self.ignored);
+        Ok(file)
+    }
+
+    /// Parses the sources, and the library files they need
+    fn parse(&self) -> Result<Parsed> {
+        let verbose = self.verbose;
+        let options = &self.options;
+        let parse_start = std::time::Instant::now();
+        let mut parsed: Vec<_> = self.inputs.sources.par_iter()
+            .chain(&self.inputs.library_files)
+            .inspect(|f| if verbose { eprintln!("Parsing {}", f.display()) } )
+            .map(|p| {
+                let defines = self.defines.clone();
+                let file = self.parse_file(p, &defines);
+                (p.clone(), defines, file)
+            })
+            .collect();
+
+        if options.macro_deps || options.propagate_defines {
+            // Files using macros defined by other sources can't parse on their own,
+            // so retry them with the definitions provided by the files that did parse
+            loop {
+                let provided: HashMap<&str, &Option<Define>> = parsed.iter()
+                    .filter_map(|(_, _, f)| f.as_ref().ok())
+                    .flat_map(|f| f.macros_defined.iter().filter_map(move |m| Some((m.as_str(), f.defines.get(m)?))))
+                    .collect();
+                let retries: Vec<_> = parsed.iter().enumerate()
+                    .filter_map(|(i, (_, _, f))| {
+                        let name = file::missing_define(f.as_ref().err()?)?;
+                        Some((i, name.to_owned(), external(provided.get(name)?)))
+                    })
+                    .collect();
+                if retries.is_empty() {
+                    break
+                }
+                let mut retried = HashSet::new();
+                for (i, name, define) in retries {
+                    parsed[i].1.insert(name, define);
+                    retried.insert(i);
+                }
+                parsed.par_iter_mut().enumerate()
+                    .filter(|(i, _)| retried.contains(i))
+                    .for_each(|(_, (p, defines, file))| *file = self.parse_file(p, defines));
+            }
+        }
+
+        let mut failures = Vec::new();
+        let mut parsed = split_failures(parsed, self.keep_going, &mut failures)?;
+
+        if !self.inputs.library_dirs.is_empty() {
+            // Library files may use other library modules in turn
+            let mut searched = HashSet::new();
+            loop {
+                let defined: HashSet<&String> = parsed.iter().flat_map(|(_, _, f)| units_defined(f)).collect();
+                let mut found = Vec::new();
+                for name in parsed.iter().flat_map(|(_, _, f)| units_used(f)) {
+                    if defined.contains(name) || !searched.insert(name.clone()) {
+                        continue
+                    }
+                    match self.inputs.find_library_file(name) {
+                        Some(path) if !parsed.iter().any(|(p, _, _)| *p == path) => found.push((name.clone(), path)),
+                        _ => (),
+                    }
+                }
+                if found.is_empty() {
+                    break
+                }
+                let library_files = found.into_par_iter()
+                    .map(|(name, path)| {
+                        if verbose {
+                            eprintln!("Parsing library file {} for {}", path.display(), name);
+                        }
+                        let defines = self.defines.clone();
+                        let file = self.parse_file(&path, &defines);
+                        (path, defines, file)
+                    })
+                    .collect();
+                parsed.extend(split_failures(library_files, self.keep_going, &mut failures)?);
+            }
+        }
+        let time = parse_start.elapsed();
+        failures.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (_, err) in &failures {
+            Diagnostic::from_error("parse", err).emit();
+        }
+
+        if options.propagate_defines {
+            let mut pass = 0;
+            loop {
+                let files: Vec<_> = parsed.iter().map(|(_, _, f)| f).collect();
+                let index: HashMap<&File, usize> = files.iter().enumerate().map(|(i, &f)| (f, i)).collect();
+                let graph = order_files(&files, options, &self.duplicates.quiet(), &self.tops, self.auto_top, 0)?;
+
+                // Every file sees the macros defined by the files compiled before it
+                let mut known: Defines = Defines::new();
+                let mut reparse = HashMap::new();
+                for file in graph.order {
+                    let i = index[file];
+                    let new_defines: Defines = file.macros_used.iter()
+                        .filter(|m| !parsed[i].1.contains_key(*m))
+                        .filter_map(|m| Some((m.clone(), known.get(m)?.clone())))
+                        .collect();
+                    if !new_defines.is_empty() {
+                        reparse.insert(i, new_defines);
+                    }
+                    // A name may be defined by a parse other than the one the defines come from
+                    for (m, define) in file.macros_defined.iter().filter_map(|m| Some((m, file.defines.get(m)?))) {
+                        known.insert(m.clone(), external(define));
+                    }
+                }
+                if reparse.is_empty() {
+                    break
+                }
+                pass += 1;
+                if pass > MAX_DEFINE_PASSES {
+                    warning!("defines", "defines still changing after {} passes, the order may be inaccurate", MAX_DEFINE_PASSES);
+                    break
+                }
+
+                parsed.par_iter_mut().enumerate()
+                    .filter_map(|(i, entry)| Some((entry, reparse.get(&i)?)))
+                    .map(|((p, defines, file), new_defines)| {
+                        if verbose {
+                            let mut names: Vec<_> = new_defines.keys().map(String::as_str).collect();
+                            names.sort_unstable();
+                            eprintln!("Re-parsing {} with {}", p.display(), names.join(", "));
+                        }
+                        defines.extend(new_defines.iter().map(|(k, v)| (k.clone(), v.clone())));
+                        *file = self.parse_file(p, defines)?;
+                        Ok(())
+                    })
+                    .collect::<Result<()>>()?;
+            }
+        }
+        Ok(Parsed { files: parsed, failures, time })
+    }
+
+    /// Picks the parsed files that are compiled on their own, and reports what may make their order inaccurate
+    fn select<'p>(&mut self, parsed: &'p Parsed) -> Vec<&'p File> {
+        let verbose = self.verbose;
+        // Sources that other sources also include would be compiled twice, their content already counts for the includer
+        let mut included_by = HashMap::new();
+        if !self.keep_included {
+            for (_, _, file) in &parsed.files {
+                for include in &file.includes {
+                    included_by.entry(canonical(include)).or_insert(&file.name);
+                }
+            }
+        }
+        let files: Vec<_> = parsed.files.iter().map(|(_, _, f)| f)
+            .filter(|f| {
+                if f.header {
+                    warning!(file: &f.name, "input", "{} looks like a header, leaving it out of the order, --compile-headers keeps it", f.name.to_string_lossy());
+                    return false
+                }
+                if let Some(includer) = included_by.get(&canonical(&f.name)) {
+                    warning!(file: &f.name, "input", "{} is included by {}, leaving it out of the order", f.name.to_string_lossy(), includer.to_string_lossy());
+                    return false
+                }
+                true
+            })
+            .collect();
+        if verbose {
+            for pattern in &self.ignored {
+                let count = files.iter().flat_map(|f| &f.ignored_uses).filter(|(name, _)| pattern.matches(name)).count();
+                eprintln!("Ignored {} uses of {}", count, pattern);
+            }
+        }
+        if self.libmap.is_some() && self.tops.is_empty() && !self.auto_top {
+            // The configs decide which definitions are compiled
+            let used: HashSet<&String> = files.iter().flat_map(|&f| units_used(f)).collect();
+            for file in &files {
+                for config in sorted(&file.configs_defined).into_iter().filter(|c| !used.contains(c)) {
+                    if verbose {
+                        eprintln!("Using config {} as a top", config);
+                    }
+                    self.tops.push(config.clone());
+                }
+            }
+        }
+
+        if verbose {
+            eprintln!("Resolving dependencies");
+        }
+        for file in &files {
+            if verbose {
+                for include in &file.includes {
+                    eprintln!("{} includes {}", file.name.to_string_lossy(), include.to_string_lossy());
+                }
+            }
+            if file.protected_regions > 0 {
+                warning!(file: &file.name, "parse", "{}: skipped {} encrypted region(s), only the visible parts are ordered",
+                          file.name.to_string_lossy(), file.protected_regions);
+            }
+            if file.skipped_includes > 0 {
+                warning!(file: &file.name, "parse", "{}: skipped {} include directive(s), anything declared in them is ignored",
+                          file.name.to_string_lossy(), file.skipped_includes);
+            }
+            if verbose && file.incomplete {
+                eprintln!("{} has syntax errors and was only partially parsed, its definitions may be incomplete", file.name.to_string_lossy());
+            }
+            if verbose {
+                for skipped in &file.skipped_nodes {
+                    warning!(file: &file.name, "parse", "{}: skipped {}", file.name.to_string_lossy(), skipped);
+                }
+            }
+            for (module, macro_name) in &file.macro_instances {
+                warning!(file: &file.name, "macro", "{}: instantiates {} through macro {}", file.name.to_string_lossy(), module, macro_name);
+            }
+            if self.lint_timescale && !file.has_timescale {
+                warning!(file: &file.name, "timescale", "{}: no `timescale directive, inherits the one from previously compiled files", file.name.to_string_lossy());
+            }
+            if verbose || self.lint {
+                for (module, port) in &file.generic_interface_ports {
+                    warning!(file: &file.name, "lint", "{}: port {} of module {} is a generic interface, its order only follows from instantiations",
+                              file.name.to_string_lossy(), port, module);
+                }
+            }
+            if verbose {
+                for nested in sorted(&file.modules_nested) {
+                    eprintln!("{} declares nested module {}, not visible to other files", file.name.to_string_lossy(), nested);
+                }
+            }
+            if verbose && !file.configs_defined.is_empty() {
+                let mut cells: Vec<_> = file.configs_used.iter().map(String::as_str).collect();
+                cells.sort_unstable();
+                eprintln!("{} configures {}", file.name.to_string_lossy(), cells.join(", "));
+            }
+        }
+        files
+    }
+
+    fn order<'f>(&self, files: &[&'f File]) -> Result<Graph<'f>> {
+        order_files(files, &self.options, &self.duplicates, &self.tops, self.auto_top, self.verbosity)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Dot,
+    Make,
+    Ninja,
+    VivadoTcl,
+    Questa,
+    VcsF,
+    XrunF,
+    Verilator,
+    Fusesoc,
+    Bazel,
+    Cmake,
+    Hdlmake,
+    Mermaid,
+    Csv,
+    Yaml,
+    Groups,
+    GroupsJson,
+}
+
+const JSON_FORMAT_HELP: &str = "Output format. dot is a Graphviz graph of the dependencies.
+make is a fragment setting SV_SOURCES_ORDERED, with a rule per file making its --object-template depend on its sources.
+ninja is a fragment building each --object-template with --compile-cmd, after the files it depends on, and a phony all target.
+vivado-tcl reads or adds the files in order, and sets the include paths and defines of the fileset.
+questa is a shell script compiling the files in order with vlog and vcom, with one call per library of --libmap.
+vcs-f is a VCS arguments file with the include paths, defines and files, which -f also reads back.
+xrun-f is a Xcelium arguments file, with a -makelib block per library of --libmap.
+verilator is a .vc file for verilator -f, with --top-module for a single --top.
+fusesoc is a CAPI2 core file with the files in a fileset, their headers as include files and the defines as parameters.
+bazel has a target per file depending on the targets of the files it needs, grouped by package, and an all target for the roots.
+cmake sets SV_SOURCES_ORDERED, SV_INCLUDE_DIRS and SV_DEFINES.
+hdlmake is a Manifest.py, or one per directory after a comment with its path.
+mermaid is a flowchart of the dependencies, with the root files in the root class.
+csv is a nodes.csv and an edges.csv section, or files named after the -o prefix like build/nodes.csv for -o build/.
+groups splits the order in numbered groups of files that only depend on the groups before them, which can be compiled
+in parallel. Files passed through are in the first group. groups-json is the same as an array of arrays of paths.
+
+json is a report of the order, with the names each file defines and uses. With a --list option it is that list instead.
+Paths follow --absolute and --relative-to, keys and arrays are sorted except for the order:
+{
+  \"order\": [path, ...],  the files in compilation order, including those passed through
+  \"files\": [{            the files in the order, sorted by path
+    \"path\": path,
+    \"passthrough\": bool,  for the files passed through without parsing, which only have a path
+    \"defines\": {\"checkers\", \"configs\", \"interfaces\", \"modules\", \"packages_and_classes\", \"primitives\", \"programs\": [name, ...]},
+    \"uses\": {the same as defines, \"macros\", \"symbols\": [name, ...]},
+    \"dependencies\": [path, ...],  the files it must be compiled after
+    \"unresolved\": [name, ...],  the modules, packages and classes it uses that no file defines
+    \"includes\": [path, ...]
+  }, ...],
+  \"unused\": [{\"path\": path, \"defines\": {...}, \"defines_nothing\": bool}, ...]  with --report-unused, the files no root needs
+  \"skipped\": [{\"path\": path, \"reason\": \"missing\"}, ...]  the sources not parsed, like those --allow-missing-sources skips
+}
+yaml is the same report as json, as YAML.";
+
+/// What the order command prints and how
+struct OrderSettings {
+    separator: String,
+    terminator: &'static str,
+    quote: Quote,
+    template: Option<String>,
+    emit_includes: bool,
+    stats: bool,
+    stats_json: bool,
+    list_modules: bool,
+    list_packages: bool,
+    list_undefined: bool,
+    print_defines: bool,
+    format: OutputFormat,
+    edge_labels: bool,
+    max_nodes: Option<usize>,
+    explain: Option<String>,
+    report_unused: bool,
+    unresolved_report: Option<PathBuf>,
+    object_template: String,
+    compile_cmd: Option<String>,
+    vivado_project: bool,
+    args_file: Option<String>,
+    sverilog: bool,
+    keep_library_dirs: bool,
+    core_name: Option<String>,
+    bazel_rule: String,
+    workspace_root: PathBuf,
+    object_depends: bool,
+    manifest_per_dir: bool,
+    passthrough_position: String,
+    /// The files of an existing filelist, which the new files get appended to
+    existing: Option<HashSet<PathBuf>>,
+    /// Where --format csv writes its files instead of the output
+    csv_prefix: Option<PathBuf>,
+}
+
+impl OrderSettings {
+    fn new(args: &ArgMatches, project: &Project) -> Result<OrderSettings> {
+        // Every path is followed by the terminator, except with a custom separator
+        let (separator, terminator) = if args.is_present("null") {
+            ("\0", "\0")
+        } else if args.is_present("space") {
+            (" ", " ")
+        } else if let Some(separator) = args.value_of("separator").or(project.separator.as_deref()) {
+            (separator, "")
+        } else {
+            ("\n", "\n")
+        };
+        let quote = match args.value_of("quote").unwrap() {
+            "always" => Quote::Always,
+            "never" => Quote::Never,
+            "shell" => Quote::Shell,
+            "tcl" => Quote::Tcl,
+            _ => Quote::Auto,
+        };
+        let template = args.value_of("template");
+        for (option, template) in [("--template", template), ("--object-template", args.value_of("object-template"))] {
+            if let Some(template) = template {
+                if !["{}", "{stem}", "{dir}"].iter().any(|p| template.contains(p)) {
+                    bail!("{} \"{}\" has no placeholder, use {{}} for the path, {{stem}} or {{dir}}", option, template);
+                }
+            }
+        }
+        let settings = OrderSettings {
+            separator: separator.to_owned(),
+            terminator,
+            quote,
+            template: template.map(str::to_owned),
+            emit_includes: args.is_present("emit-includes"),
+            stats: args.is_present("stats"),
+            stats_json: args.is_present("stats-json"),
+            list_modules: args.is_present("list-modules"),
+            list_packages: args.is_present("list-packages"),
+            list_undefined: args.is_present("list-undefined"),
+            print_defines: args.is_present("print-defines"),
+            format: match args.value_of("format").unwrap() {
+                "json" => OutputFormat::Json,
+                "dot" => OutputFormat::Dot,
+                "make" => OutputFormat::Make,
+                "ninja" => OutputFormat::Ninja,
+                "vivado-tcl" => OutputFormat::VivadoTcl,
+                "questa" => OutputFormat::Questa,
+                "vcs-f" => OutputFormat::VcsF,
+                "xrun-f" => OutputFormat::XrunF,
+                "verilator" => OutputFormat::Verilator,
+                "fusesoc" => OutputFormat::Fusesoc,
+                "bazel" => OutputFormat::Bazel,
+                "cmake" => OutputFormat::Cmake,
+                "hdlmake" => OutputFormat::Hdlmake,
+                "mermaid" => OutputFormat::Mermaid,
+                "csv" => OutputFormat::Csv,
+                "yaml" => OutputFormat::Yaml,
+                "groups" => OutputFormat::Groups,
+                "groups-json" => OutputFormat::GroupsJson,
+                _ => OutputFormat::Text,
+            },
+            edge_labels: args.is_present("edge-labels"),
+            explain: args.value_of("explain").map(str::to_owned),
+            report_unused: args.is_present("report-unused"),
+            unresolved_report: args.value_of_os("unresolved-report").map(PathBuf::from),
+            max_nodes: args.value_of("max-nodes")
+                .map(|count| count.parse().ok().filter(|&count| count > 0).with_context(|| format!("Invalid --max-nodes {}", count)))
+                .transpose()?,
+            object_template: args.value_of("object-template").unwrap().to_owned(),
+            compile_cmd: args.value_of("compile-cmd").map(str::to_owned),
+            vivado_project: args.value_of("vivado-mode") == Some("project"),
+            args_file: args.value_of("args-file").map(str::to_owned),
+            sverilog: args.is_present("sverilog"),
+            keep_library_dirs: args.is_present("keep-library-dirs"),
+            core_name: args.value_of("core-name").map(str::to_owned),
+            bazel_rule: args.value_of("bazel-rule").unwrap().to_owned(),
+            object_depends: args.is_present("object-depends"),
+            manifest_per_dir: args.is_present("manifest-per-dir"),
+            workspace_root: {
+                let root = Path::new(args.value_of_os("workspace-root").unwrap());
+                std::fs::canonicalize(root).with_context(|| format!("Invalid --workspace-root {}", root.display()))?
+            },
+            passthrough_position: args.value_of("passthrough-position").unwrap().to_owned(),
+            existing: args.value_of_os("only-new").map(|path| -> Result<HashSet<PathBuf>> {
+                let mut existing = Inputs::default();
+                existing.add_filelist(Path::new(path), false)?;
+                Ok(existing.sources.iter().chain(&existing.library_files).map(|p| canonical(p)).collect())
+            }).transpose()?,
+            csv_prefix: args.value_of_os("output").map(PathBuf::from)
+                .filter(|path| args.value_of("format") == Some("csv") && path != Path::new("-")),
+        };
+        let listing = settings.list_modules || settings.list_packages || settings.list_undefined || settings.print_defines;
+        if settings.format == OutputFormat::Ninja && settings.compile_cmd.is_none() {
+            bail!("--format ninja needs a --compile-cmd");
+        }
+        if !matches!(settings.format, OutputFormat::Text | OutputFormat::Json) && listing {
+            bail!("--format {} can't be used with --list-modules, --list-packages, --list-undefined or --print-defines", args.value_of("format").unwrap());
+        }
+        if settings.edge_labels && settings.format != OutputFormat::Dot {
+            bail!("--edge-labels is only supported with --format dot");
+        }
+        if settings.explain.is_some() && settings.format != OutputFormat::Text {
+            bail!("--explain is only supported with --format text");
+        }
+        if settings.report_unused && listing {
+            bail!("--report-unused can't be used with --list-modules, --list-packages, --list-undefined or --print-defines");
+        }
+        if settings.unresolved_report.is_some() && listing {
+            bail!("--unresolved-report can't be used with --list-modules, --list-packages, --list-undefined or --print-defines");
+        }
+        if settings.explain.is_some() && listing {
+            bail!("--explain can't be used with --list-modules, --list-packages, --list-undefined or --print-defines");
+        }
+        if settings.max_nodes.is_some() && settings.format != OutputFormat::Mermaid {
+            bail!("--max-nodes is only supported with --format mermaid");
+        }
+        if settings.args_file.is_some() && settings.format != OutputFormat::Questa {
+            bail!("--args-file is only supported with --format questa");
+        }
+        if settings.sverilog && settings.format != OutputFormat::VcsF {
+            bail!("--sverilog is only supported with --format vcs-f");
+        }
+        if settings.format == OutputFormat::Fusesoc && settings.core_name.is_none() {
+            bail!("--format fusesoc needs a --core-name");
+        }
+        if settings.object_depends && settings.format != OutputFormat::Cmake {
+            bail!("--object-depends is only supported with --format cmake");
+        }
+        if settings.manifest_per_dir && settings.format != OutputFormat::Hdlmake {
+            bail!("--manifest-per-dir is only supported with --format hdlmake");
+        }
+        if settings.keep_library_dirs && settings.format != OutputFormat::Verilator {
+            bail!("--keep-library-dirs is only supported with --format verilator");
+        }
+        Ok(settings)
+    }
+}
+
+/// Prints the files in compilation order, or one of the lists instead.
+/// Returns the output, and the number of names listed by --list-undefined
+fn order_command(settings: &OrderSettings, project: &Project, parsed: &Parsed, files: &[&File],
+                 output_name: &dyn Fn(&Path) -> Result<String>) -> Result<(String, usize)> {
+    let mut output = String::new();
+    if settings.emit_includes {
+        for file in files {
+            let includes: Vec<_> = file.includes.iter().map(|i| i.to_string_lossy()).collect();
+            writeln!(output, "{}: {}", file.name.to_string_lossy(), includes.join(" "))?;
+        }
+    }
+
+    let json = settings.format == OutputFormat::Json;
+    if settings.list_modules || settings.list_packages {
+        list_definitions(&mut output, files, settings.list_modules, settings.list_packages, json, output_name)?;
+        return Ok((output, 0))
+    } else if settings.print_defines {
+        print_file_defines(&mut output, files, json, output_name)?;
+        return Ok((output, 0))
+    } else if settings.list_undefined {
+        let undefined = list_undefined_names(&mut output, files, json, output_name)?;
+        return Ok((output, undefined))
+    }
+
+    let graph = project.order(files)?;
+    if let Some(target) = &settings.explain {
+        explain(&mut output, &graph, find_target(files, target)?, output_name)?;
+        return Ok((output, 0))
+    }
+    let ordered: HashSet<&File> = graph.order.iter().copied().collect();
+    let unused: Vec<&File> = files.iter().copied().filter(|file| settings.report_unused && !ordered.contains(file)).collect();
+    if !unused.is_empty() && !matches!(settings.format, OutputFormat::Json | OutputFormat::Yaml) {
+        let mut lines = Vec::new();
+        for &file in &unused {
+            let kinds = [("module", &file.modules_defined), ("interface", &file.interfaces_defined), ("program", &file.programs_defined),
+                         ("checker", &file.checkers_defined), ("primitive", &file.udps_defined), ("config", &file.configs_defined),
+                         ("package/class", &file.packages_defined)];
+            let definitions: Vec<_> = kinds.iter().flat_map(|(kind, names)| sorted(names.iter()).into_iter().map(move |name| format!("{} {}", kind, name))).collect();
+            let definitions = if definitions.is_empty() { "defines nothing".to_owned() } else { definitions.join(", ") };
+            lines.push(format!("    {}: {}", output_name(&file.name)?, definitions));
+        }
+        lines.sort_unstable();
+        eprintln!("{} files are not needed by any root:", unused.len());
+        for line in lines {
+            eprintln!("{}", line);
+        }
+    }
+    if let Some(path) = &settings.unresolved_report {
+        let report = formats::UnresolvedReport::new(&graph, output_name)?;
+        if project.verbose {
+            eprintln!("Writing the unresolved names to {}", path.display());
+        }
+        write_atomic(path, &(serde_json::to_string_pretty(&report)? + "\n"))?;
+    }
+    let mut order = graph.order.clone();
+    let on_unresolved = project.on_unresolved;
+    if on_unresolved != OnUnresolved::Ignore {
+        let unresolved = find_unresolved(files, &order);
+        for (file, names) in &unresolved {
+            if on_unresolved == OnUnresolved::Error {
+                Diagnostic::error("unresolved", format!("{}: unresolved {}", file, names.join(", "))).file(file).emit();
+            } else {
+                warning!(file: file, "unresolved", "{}: unresolved {}", file, names.join(", "));
+            }
+        }
+        if on_unresolved == OnUnresolved::Error && !unresolved.is_empty() {
+            let count: usize = unresolved.iter().map(|(_, names)| names.len()).sum();
+            bail!("{} names used by {} files are not defined by any source", count, unresolved.len());
+        }
+    }
+
+    let mut passthrough = project.passthrough.clone();
+    if let Some(existing) = &settings.existing {
+        let count = order.len();
+        order.retain(|f| !existing.contains(&canonical(&f.name)));
+        passthrough.retain(|(_, path)| !existing.contains(&canonical(path)));
+        if project.verbose {
+            eprintln!("Leaving out {} files already listed", count - order.len());
+        }
+    }
+    let mut sources = order.iter()
+        .map(|file| Ok(formats::Source {
+            name: file.name.clone(),
+            path: output_name(&file.name)?,
+            library: file.work_library.clone(),
+            searched: !project.inputs.sources.contains(&file.name) && !project.inputs.library_files.contains(&file.name),
+        }))
         .collect::<Result<Vec<_>>>()?;
+    // Positions are by input order, so they may shift a bit as the other files move
+    for (i, (position, path)) in passthrough.iter().enumerate() {
+        let index = match settings.passthrough_position.as_str() {
+            "front" => i,
+            "back" => sources.len(),
+            _ => (*position).min(sources.len()),
+        };
+        let library = project.libmap.as_ref().map(|map| map.library_of(path));
+        sources.insert(index, formats::Source { name: path.clone(), path: output_name(path)?, library, searched: false });
+    }
+    let mut names: Vec<_> = sources.iter().map(|source| settings.quote.list(&source.path)).collect();
+    if let Some(template) = &settings.template {
+        names = names.iter().map(|name| apply_template(template, name)).collect();
+    }
+    if project.verbose {
+        eprintln!("Ordered {} source files", names.len());
+    }
+    if settings.stats || settings.stats_json {
+        let count = |set: fn(&File) -> usize| files.iter().map(|&f| set(f)).sum::<usize>();
+        let unresolved: usize = find_unresolved(files, files).iter().map(|(_, names)| names.len()).sum();
+        let stats = [
+            ("files", json!(files.len())),
+            ("modules", json!(count(|f| units_defined(f).count()))),
+            ("packages_and_classes", json!(count(|f| f.packages_defined.len()))),
+            ("dependencies", json!(graph.stats.edges)),
+            ("unresolved", json!(unresolved)),
+            ("roots", json!(graph.stats.roots)),
+            ("longest_chain", json!(graph.stats.longest_chain)),
+            ("parse_seconds", json!((parsed.time.as_secs_f64() * 1000.).round() / 1000.)),
+            ("skipped", json!(project.missing_sources.iter().map(|p| p.to_string_lossy()).collect::<Vec<_>>())),
+        ];
+        if settings.stats_json {
+            let map: serde_json::Map<_, _> = stats.iter().map(|(key, value)| (key.to_string(), value.clone())).collect();
+            eprintln!("{}", serde_json::Value::Object(map));
+        } else {
+            for (key, value) in &stats {
+                eprintln!("{}: {}", key, value);
+            }
+        }
+    }
+    let setup = formats::Setup {
+        incdirs: project.inputs.incdirs.iter().map(|dir| output_name(dir).unwrap_or_else(|_| dir.to_string_lossy().into_owned())).collect(),
+        defines: project.define_args.clone(),
+        library_dirs: project.inputs.library_dirs.iter().map(|dir| output_name(dir).unwrap_or_else(|_| dir.to_string_lossy().into_owned())).collect(),
+        libext: project.inputs.libext.clone(),
+        tops: project.tops.clone(),
+    };
+    match settings.format {
+        OutputFormat::Dot => formats::dot(&mut output, &graph, &order, settings.edge_labels, output_name)?,
+        OutputFormat::Groups | OutputFormat::GroupsJson => {
+            let cycles = find_cycles(&order, &graph.deps);
+            for cycle in &cycles {
+                let names: Vec<_> = cycle.iter().map(|file| file.name.to_string_lossy()).collect();
+                warning!(file: &cycle[0].name, "cycle", "{} depend on each other, they are all in the same group", names.join(", "));
+            }
+            let mut groups = compile_groups(&order, &graph.deps, &cycles).iter()
+                .map(|group| group.iter().map(|file| output_name(&file.name)).collect::<Result<Vec<_>>>())
+                .collect::<Result<Vec<_>>>()?;
+            let mut passed = passthrough.iter().map(|(_, path)| output_name(path)).collect::<Result<Vec<_>>>()?;
+            if !passed.is_empty() {
+                if groups.is_empty() {
+                    groups.push(Vec::new());
+                }
+                passed.append(&mut groups[0]);
+                groups[0] = passed;
+            }
+            if settings.format == OutputFormat::GroupsJson {
+                writeln!(output, "{}", serde_json::to_string_pretty(&groups)?)?;
+            } else {
+                for (i, group) in groups.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(output)?;
+                    }
+                    writeln!(output, "# group {}", i)?;
+                    for path in group {
+                        let name = settings.quote.list(path);
+                        let name = settings.template.as_ref().map(|template| apply_template(template, &name)).unwrap_or(name);
+                        writeln!(output, "{}", name)?;
+                    }
+                }
+            }
+        }
+        OutputFormat::Mermaid => formats::mermaid(&mut output, &graph, &order, settings.max_nodes, output_name)?,
+        OutputFormat::Csv => {
+            let (nodes, edges) = formats::csv(&graph, &order, output_name)?;
+            if let Some(prefix) = &settings.csv_prefix {
+                for (name, table) in [("nodes.csv", &nodes), ("edges.csv", &edges)] {
+                    let mut path = prefix.clone().into_os_string();
+                    path.push(name);
+                    if project.verbose {
+                        eprintln!("Writing the {} table to {}", name, Path::new(&path).display());
+                    }
+                    write_atomic(Path::new(&path), table)?;
+                }
+            } else {
+                write!(output, "# nodes.csv\n{}\n# edges.csv\n{}", nodes, edges)?;
+            }
+        }
+        OutputFormat::Make => formats::make(&mut output, &graph, &order, &sources, &settings.object_template, output_name)?,
+        OutputFormat::Ninja => {
+            let compile_cmd = settings.compile_cmd.as_deref().unwrap();
+            formats::ninja(&mut output, &graph, &order, compile_cmd, &settings.object_template, output_name)?
+        }
+        OutputFormat::VivadoTcl => formats::vivado_tcl(&mut output, &sources, &setup, settings.vivado_project)?,
+        OutputFormat::Questa => {
+            let args_file = settings.args_file.as_deref();
+            if let Some(arguments) = formats::questa(&mut output, &sources, &setup, args_file)? {
+                let path = Path::new(args_file.unwrap());
+                if project.verbose {
+                    eprintln!("Writing the vlog arguments to {}", path.display());
+                }
+                write_atomic(path, &arguments)?;
+            }
+        }
+        OutputFormat::VcsF => formats::vcs_f(&mut output, &sources, &setup, settings.quote, settings.sverilog)?,
+        OutputFormat::XrunF => formats::xrun_f(&mut output, &sources, &setup, settings.quote)?,
+        OutputFormat::Verilator => formats::verilator(&mut output, &sources, &setup, settings.quote, settings.keep_library_dirs)?,
+        OutputFormat::Fusesoc => {
+            // Headers passed as sources are include files as well
+            let header_sources = parsed.files.iter().filter(|(_, _, file)| file.header).map(|(_, _, file)| &file.name);
+            let mut headers = order.iter().flat_map(|file| &file.includes).chain(header_sources)
+                .map(|header| output_name(header))
+                .collect::<Result<Vec<_>>>()?;
+            headers.sort_unstable();
+            headers.dedup();
+            formats::fusesoc(&mut output, &sources, &headers, &setup, settings.core_name.as_deref().unwrap())?
+        }
+        OutputFormat::Bazel => {
+            // Also the cycles out of the order, which would otherwise be missing targets
+            let cycles = find_cycles(files, &graph.deps);
+            for cycle in &cycles {
+                let names: Vec<_> = cycle.iter().map(|file| file.name.to_string_lossy()).collect();
+                Diagnostic::error("cycle", format!("{} depend on each other, Bazel doesn't allow cycles", names.join(", "))).file(&cycle[0].name).emit();
+            }
+            if !cycles.is_empty() {
+                bail!("{} dependency cycles can't be written as Bazel targets", cycles.len());
+            }
+            formats::bazel(&mut output, &graph, &order, &settings.bazel_rule, &settings.workspace_root)?
+        }
+        OutputFormat::Cmake => {
+            let object_depends = settings.object_depends.then_some(&order[..]);
+            formats::cmake(&mut output, &sources, &setup, object_depends, output_name)?
+        }
+        OutputFormat::Hdlmake if settings.manifest_per_dir => {
+            formats::hdlmake_per_dir(&mut output, &graph, &order, &sources, &project.inputs.incdirs, &setup.defines, output_name)?
+        }
+        OutputFormat::Hdlmake => formats::hdlmake(&mut output, &sources, &setup)?,
+        OutputFormat::Json | OutputFormat::Yaml => {
+            let passed = passthrough.iter().map(|(_, path)| output_name(path)).collect::<Result<Vec<_>>>()?;
+            let order_paths = sources.into_iter().map(|source| source.path).collect();
+            let mut report = formats::Report::new(&graph, files, &order, order_paths, passed, output_name)?;
+            if settings.report_unused {
+                report.add_unused(&unused, output_name)?;
+            }
+            report.add_missing(&project.missing_sources, output_name);
+            let report = serde_json::to_value(&report)?;
+            if settings.format == OutputFormat::Json {
+                writeln!(output, "{}", serde_json::to_string_pretty(&report)?)?;
+            } else {
+                formats::yaml(&mut output, &report, 0)?;
+            }
+        }
+        OutputFormat::Text => {
+            output.push_str(&names.join(&settings.separator));
+            if !names.is_empty() {
+                output.push_str(settings.terminator);
+            }
+        }
+    }
+    Ok((output, 0))
+}
 
-    let mut module_defs: HashMap<String, &File> = HashMap::new();
-    let mut package_defs: HashMap<String, &File> = HashMap::new();
+/// Prints the dependency graph of the files, as DOT or as the JSON report
+fn graph_command(args: &ArgMatches, project: &Project, files: &[&File], output_name: &dyn Fn(&Path) -> Result<String>) -> Result<String> {
+    let edge_labels = args.is_present("edge-labels");
+    if edge_labels && args.value_of("format") != Some("dot") {
+        bail!("--edge-labels is only supported with --format dot");
+    }
+    let graph = project.order(files)?;
+    let mut output = String::new();
+    if args.value_of("format") == Some("json") {
+        let order_paths = graph.order.iter().map(|file| output_name(&file.name)).collect::<Result<Vec<_>>>()?;
+        let report = formats::Report::new(&graph, files, &graph.order, order_paths, Vec::new(), output_name)?;
+        writeln!(output, "{}", serde_json::to_string_pretty(&report)?)?;
+    } else {
+        formats::dot(&mut output, &graph, &graph.order, edge_labels, output_name)?;
+    }
+    Ok(output)
+}
 
-    if verbose {
-        println!("Resolving dependencies");
+/// Prints the files defining a name, or those a target needs or is needed by, in compilation order
+fn query_command(args: &ArgMatches, project: &Project, files: &[&File], output_name: &dyn Fn(&Path) -> Result<String>) -> Result<String> {
+    let query = args.value_of("query").unwrap();
+    let target = args.value_of("target").unwrap();
+    let defining: Vec<&File> = files.iter().copied()
+        .filter(|f| units_defined(f).chain(&f.packages_defined).any(|name| name == target))
+        .collect();
+    let mut output = String::new();
+    if query == "find-module" {
+        if defining.is_empty() {
+            bail!("{} is not defined by any source", target);
+        }
+        for file in defining {
+            writeln!(output, "{}", output_name(&file.name)?)?;
+        }
+        return Ok(output)
+    }
+
+    let target_file = find_target(files, target)?;
+    let graph = project.order(files)?;
+    let mut reached = HashSet::new();
+    let mut stack = vec![target_file];
+    while let Some(file) = stack.pop() {
+        let next: Vec<&File> = if query == "deps-of" {
+            graph.deps.get(file).into_iter().flatten().copied().collect()
+        } else {
+            graph.deps.iter().filter(|(_, deps)| deps.contains(file)).map(|(&user, _)| user).collect()
+        };
+        stack.extend(next.into_iter().filter(|f| reached.insert(*f)));
+    }
+    reached.remove(target_file);
+    for file in graph.order.iter().filter(|f| reached.contains(*f)) {
+        writeln!(output, "{}", output_name(&file.name)?)?;
+    }
+    Ok(output)
+}
+
+/// A path names a file, anything else is a name defined by the sources
+fn find_target<'f>(files: &[&'f File], target: &str) -> Result<&'f File> {
+    let target_path = canonical(Path::new(target));
+    files.iter().copied().find(|f| canonical(&f.name) == target_path)
+        .or_else(|| files.iter().copied().find(|f| units_defined(f).chain(&f.packages_defined).any(|name| name == target)))
+        .with_context(|| format!("{} is neither a source nor defined by one", target))
+}
+
+/// Why a file is in the order: what it depends on and what uses it, with the names causing it,
+/// and the paths from the roots down to it
+fn explain(output: &mut String, graph: &Graph, target: &File, output_name: &dyn Fn(&Path) -> Result<String>) -> Result<()> {
+    let reasons = |file: &File, dep: &File| {
+        let reasons: Vec<_> = graph.reasons[&(file, dep)].iter().map(|r| format!("{} {}", r.kind, r.name)).collect();
+        reasons.join(", ")
+    };
+    let by_name = |files: &mut Vec<&File>| files.sort_by(|a, b| a.name.cmp(&b.name));
+    writeln!(output, "{}", output_name(&target.name)?)?;
+    match graph.order.iter().position(|&file| file == target) {
+        Some(position) => writeln!(output, "  is number {} of the {} files in the order", position + 1, graph.order.len())?,
+        None => writeln!(output, "  is not in the order, no root needs it")?,
+    }
+    let mut deps: Vec<_> = graph.deps[target].iter().copied().collect();
+    by_name(&mut deps);
+    writeln!(output, "  depends on:{}", if deps.is_empty() { " nothing" } else { "" })?;
+    for dep in deps {
+        writeln!(output, "    {} for {}", output_name(&dep.name)?, reasons(target, dep))?;
+    }
+    let mut users: Vec<_> = graph.deps.iter().filter(|(_, deps)| deps.contains(target)).map(|(&user, _)| user).collect();
+    by_name(&mut users);
+    writeln!(output, "  is used by:{}", if users.is_empty() { " nothing" } else { "" })?;
+    for &user in &users {
+        writeln!(output, "    {} for {}", output_name(&user.name)?, reasons(user, target))?;
+    }
+
+    // The files the target can be reached from, then the tree of the paths going through them
+    let mut reaching = HashSet::new();
+    let mut stack = vec![target];
+    while let Some(file) = stack.pop() {
+        if reaching.insert(file) {
+            stack.extend(graph.deps.iter().filter(|(_, deps)| deps.contains(file)).map(|(&user, _)| user));
+        }
+    }
+    let roots: Vec<_> = graph.roots.iter().copied().filter(|root| reaching.contains(root)).collect();
+    writeln!(output, "  is reached from the roots:{}", if roots.is_empty() { " none" } else { "" })?;
+    // Subtrees are only written once, they are marked as above after that
+    let mut shown = HashSet::new();
+    let mut stack: Vec<(&File, Option<&File>, usize)> = roots.iter().rev().map(|&root| (root, None, 2)).collect();
+    while let Some((file, parent, depth)) = stack.pop() {
+        let reason = parent.map(|parent| format!(" for {}", reasons(parent, file))).unwrap_or_default();
+        let repeated = file != target && !shown.insert(file);
+        writeln!(output, "{}{}{}{}", "  ".repeat(depth), output_name(&file.name)?, reason, if repeated { " (as above)" } else { "" })?;
+        if file == target || repeated {
+            continue
+        }
+        let mut deps: Vec<_> = graph.deps[file].iter().copied().filter(|dep| reaching.contains(dep)).collect();
+        by_name(&mut deps);
+        stack.extend(deps.into_iter().rev().map(|dep| (dep, Some(file), depth + 1)));
+    }
+    Ok(())
+}
+
+/// Checks that every file of an order comes after the files it depends on
+fn check_command(args: &ArgMatches, project: &Project, files: &[&File], output_name: &dyn Fn(&Path) -> Result<String>) -> Result<String> {
+    let order_path = Path::new(args.value_of_os("order").unwrap());
+    let mut listed = Inputs::default();
+    listed.add_filelist(order_path, false)?;
+    let positions: HashMap<PathBuf, usize> = listed.sources.iter().enumerate().map(|(i, p)| (canonical(p), i)).collect();
+    // Everything is checked, not only what the tops need
+    let graph = order_files(files, &project.options, &project.duplicates, &[], false, project.verbosity)?;
+
+    let mut problems = Vec::new();
+    for &file in files {
+        let position = match positions.get(&canonical(&file.name)) {
+            Some(&position) => position,
+            None => continue,
+        };
+        let mut deps: Vec<_> = graph.deps[file].iter().collect();
+        deps.sort_by(|a, b| a.name.cmp(&b.name));
+        for dep in deps {
+            match positions.get(&canonical(&dep.name)) {
+                Some(&dep_position) if dep_position > position => {
+                    problems.push((format!("{} is listed before {}, which it depends on", output_name(&file.name)?, output_name(&dep.name)?), &file.name));
+                }
+                None => {
+                    problems.push((format!("{} depends on {}, which isn't listed", output_name(&file.name)?, output_name(&dep.name)?), &file.name));
+                }
+                _ => (),
+            }
+        }
+    }
+    problems.sort_unstable();
+    for (problem, file) in &problems {
+        Diagnostic::error("order", problem.clone()).file(file).emit();
+    }
+    if !problems.is_empty() {
+        bail!("{} has {} ordering problems", order_path.display(), problems.len());
+    }
+    if project.verbose {
+        eprintln!("{} is in a valid order", order_path.display());
+    }
+    Ok(String::new())
+}
+
+/// Lists what each name is and which files define it, flagging the names defined more than once
+fn list_definitions(output: &mut String, files: &[&File], modules: bool, packages: bool, json: bool,
+                    output_name: &dyn Fn(&Path) -> Result<String>) -> Result<()> {
+    let mut definitions: BTreeMap<(&str, &str), Vec<String>> = BTreeMap::new();
+    for &file in files {
+        let mut kinds = Vec::new();
+        if modules {
+            kinds.extend([("module", &file.modules_defined), ("interface", &file.interfaces_defined),
+                          ("program", &file.programs_defined), ("checker", &file.checkers_defined),
+                          ("primitive", &file.udps_defined), ("config", &file.configs_defined)]);
+        }
+        if packages {
+            kinds.push(("package/class", &file.packages_defined));
+        }
+        for (kind, names) in kinds {
+            for name in names {
+                definitions.entry((name, kind)).or_default().push(output_name(&file.name)?);
+            }
+        }
+    }
+
+    if json {
+        let list: Vec<_> = definitions.iter()
+            .map(|((name, kind), files)| json!({
+                "name": name,
+                "kind": kind,
+                "files": files,
+                "duplicate": files.len() > 1,
+            }))
+            .collect();
+        writeln!(output, "{}", serde_json::to_string_pretty(&list)?)?;
+    } else {
+        for ((name, kind), files) in &definitions {
+            let duplicate = if files.len() > 1 { " (duplicate)" } else { "" };
+            writeln!(output, "{} {}: {}{}", kind, name, files.join(", "), duplicate)?;
+        }
+    }
+    Ok(())
+}
+
+/// Lists the macros defined at the end of each file, with the header defining them
+fn print_file_defines(output: &mut String, files: &[&File], json: bool, output_name: &dyn Fn(&Path) -> Result<String>) -> Result<()> {
+    let mut list = Vec::new();
+    for &file in files {
+        let name = output_name(&file.name)?;
+        let mut macros = Vec::new();
+        for (macro_name, define) in file.defines.iter().collect::<BTreeMap<_, _>>() {
+            let arguments: Vec<_> = define.iter()
+                .flat_map(|d| &d.arguments)
+                .map(|(arg, default)| match default {
+                    Some(default) => format!("{}={}", arg, default),
+                    None => arg.clone(),
+                })
+                .collect();
+            let text = define.as_ref().and_then(|d| d.text.as_ref());
+            let value = text.map(|t| t.text.trim().to_owned());
+            let origin = text.and_then(|t| t.origin.as_ref())
+                .filter(|(path, _)| *path != file.name)
+                .map(|(path, _)| output_name(path))
+                .transpose()?;
+            macros.push((macro_name, arguments, value, origin));
+        }
+
+        if json {
+            let macros: Vec<_> = macros.iter()
+                .map(|(macro_name, arguments, value, origin)| json!({
+                    "name": macro_name,
+                    "arguments": arguments,
+                    "value": value,
+                    "origin": origin,
+                }))
+                .collect();
+            list.push(json!({ "file": name, "defines": macros }));
+            continue
+        }
+        writeln!(output, "{}:", name)?;
+        for (macro_name, arguments, value, origin) in macros {
+            write!(output, "  {}", macro_name)?;
+            if !arguments.is_empty() {
+                write!(output, "({})", arguments.join(", "))?;
+            }
+            if let Some(value) = value.filter(|v| !v.is_empty()) {
+                write!(output, " = {}", value.replace('\n', " "))?;
+            }
+            if let Some(origin) = origin {
+                write!(output, " (from {})", origin)?;
+            }
+            writeln!(output)?;
+        }
+    }
+    if json {
+        writeln!(output, "{}", serde_json::to_string_pretty(&list)?)?;
+    }
+    Ok(())
+}
+
+/// Lists the names used but defined by no file, with the files using them. Returns how many there are
+fn list_undefined_names(output: &mut String, files: &[&File], json: bool,
+                        output_name: &dyn Fn(&Path) -> Result<String>) -> Result<usize> {
+    let defined: HashSet<&String> = files.iter()
+        .flat_map(|&f| units_defined(f).chain(&f.packages_defined))
+        .collect();
+    let mut undefined: BTreeMap<(&str, &str), Vec<String>> = BTreeMap::new();
+    for &file in files {
+        let uses = units_used(file).map(|name| (name, "module"))
+            .chain(file.packages_used.iter().map(|name| (name, "package/class")));
+        for (name, kind) in uses.filter(|(name, _)| !defined.contains(name)) {
+            undefined.entry((name, kind)).or_default().push(output_name(&file.name)?);
+        }
+    }
+
+    if json {
+        let list: Vec<_> = undefined.iter()
+            .map(|((name, kind), files)| json!({
+                "name": name,
+                "kind": kind,
+                "used_by": files,
+            }))
+            .collect();
+        writeln!(output, "{}", serde_json::to_string_pretty(&list)?)?;
+    } else {
+        for ((name, kind), files) in &undefined {
+            writeln!(output, "{} {}: used by {}", kind, name, files.join(", "))?;
+        }
+    }
+    Ok(undefined.len())
+}
+
+/// Takes out the files that failed to parse with keep_going, otherwise returns the first error
+fn split_failures(parsed: Vec<(PathBuf, Defines, Result<File>)>, keep_going: bool,
+                  failures: &mut Vec<(PathBuf, anyhow::Error)>) -> Result<Vec<(PathBuf, Defines, File)>> {
+    let mut files = Vec::new();
+    for (path, defines, file) in parsed {
+        match file {
+            Ok(file) => files.push((path, defines, file)),
+            Err(err) if keep_going => failures.push((path, err)),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(files)
+}
+
+/// Replaces the placeholders of a --template with parts of the path
+fn apply_template(template: &str, path: &str) -> String {
+    let path_ref = Path::new(path);
+    let stem = path_ref.file_stem().unwrap_or_default().to_string_lossy();
+    let dir = match path_ref.parent().map(Path::to_string_lossy) {
+        Some(dir) if !dir.is_empty() => dir,
+        _ => ".".into(),
+    };
+    template.replace("{stem}", &stem).replace("{dir}", &dir).replace("{}", path)
+}
+
+/// Resolves a path for comparisons, keeping it as is if it doesn't exist
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+/// Expresses an absolute path relative to an absolute base, going up with .. if needed
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let common = path.components().zip(base.components()).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        // Nothing in common, like on different Windows drives
+        return path.to_owned()
+    }
+    let ups = base.components().skip(common).map(|_| std::path::Component::ParentDir);
+    let relative: PathBuf = ups.chain(path.components().skip(common)).collect();
+    if relative.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        relative
+    }
+}
+
+/// Writes next to the destination first, so readers never see a partial file
+fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    write_atomic_with(path, |file| std::io::Write::write_all(file, contents.as_bytes()))
+}
+
+fn write_atomic_with(path: &Path, write: impl FnOnce(&mut std::fs::File) -> std::io::Result<()>) -> Result<()> {
+    let file_name = path.file_name().with_context(|| format!("Invalid output path {}", path.display()))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = path.with_file_name(temp_name);
+    let written = std::fs::File::create(&temp_path).and_then(|mut file| write(&mut file));
+    if let Err(err) = written {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(err).with_context(|| format!("Failed to write {}", temp_path.display()));
     }
-    for file in &files {
+    std::fs::rename(&temp_path, path).with_context(|| {
+        let _ = std::fs::remove_file(&temp_path);
+        format!("Failed to write {}", path.display())
+    })
+}
+
+/// Reads defines from a file of +define+ plusargs or `define lines, as NAME(ARGS)=VALUE for parse_define
+fn read_defines_file(path: &Path) -> Result<Vec<String>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read defines from {}", path.display()))?;
+    let mut defines = Vec::new();
+    let mut lines = text.lines().enumerate();
+    while let Some((i, line)) = lines.next() {
+        let at = || format!("{}:{}", path.display(), i + 1);
+        let line = line.trim();
+        if let Some(directive) = line.strip_prefix("`define") {
+            // Macro bodies continue on the next line after a backslash
+            let mut body = directive.to_owned();
+            while body.ends_with('\\') {
+                body.pop();
+                body.push('\n');
+                match lines.next() {
+                    Some((_, next)) => body.push_str(next),
+                    None => break,
+                }
+            }
+            let body = body.trim_start();
+            let name_end = body.find(|c: char| c.is_whitespace() || c == '(').unwrap_or(body.len());
+            let (name, mut rest) = body.split_at(name_end);
+            let mut define = name.to_owned();
+            if rest.starts_with('(') {
+                let args_end = rest.find(')').with_context(|| format!("{}: unclosed argument list", at()))?;
+                define.push_str(&rest[..=args_end]);
+                rest = &rest[args_end + 1..];
+            }
+            let rest = rest.trim();
+            if !rest.is_empty() {
+                define.push('=');
+                define.push_str(rest);
+            }
+            parse_define(&define).with_context(at)?;
+            defines.push(define);
+        } else {
+            for word in filelist::strip_comment(line).split_whitespace() {
+                let values = word.strip_prefix("+define+").with_context(|| format!("{}: expected +define+ or `define, found {}", at(), word))?;
+                for define in filelist::split_plusarg(values) {
+                    parse_define(define).with_context(at)?;
+                    defines.push(define.to_owned());
+                }
+            }
+        }
+    }
+    Ok(defines)
+}
+
+/// A define of another source, passed to a parse like a command line define, so that
+/// its expansions aren't taken for text included from that source
+fn external(define: &Option<Define>) -> Option<Define> {
+    let mut define = define.clone()?;
+    if let Some(text) = &mut define.text {
+        text.origin = None;
+    }
+    Some(define)
+}
+
+/// Parses a command line macro definition, as NAME, NAME=VALUE or NAME(ARGS)=VALUE
+fn parse_define(arg: &str) -> Result<(String, Option<Define>)> {
+    let name_end = arg.find(['(', '=']).unwrap_or(arg.len());
+    let (name, mut rest) = arg.split_at(name_end);
+    if name.is_empty() {
+        bail!("Invalid define \"{}\", expected NAME, NAME=VALUE or NAME(ARGS)=VALUE", arg);
+    }
+
+    let mut arguments = Vec::new();
+    if let Some(args_rest) = rest.strip_prefix('(') {
+        let args_end = args_rest.find(')').with_context(|| format!("Unclosed argument list in define \"{}\"", arg))?;
+        for macro_arg in args_rest[..args_end].split(',') {
+            let (arg_name, default) = match macro_arg.split_once('=') {
+                Some((arg_name, default)) => (arg_name, Some(default.trim().to_owned())),
+                None => (macro_arg, None),
+            };
+            arguments.push((arg_name.trim().to_owned(), default));
+        }
+        rest = &args_rest[args_end+1..];
+    }
+
+    let text = match rest.strip_prefix('=') {
+        Some(text) => Some(DefineText::new(text.to_owned(), None)),
+        None if rest.is_empty() => None,
+        None => bail!("Invalid define \"{}\", expected NAME, NAME=VALUE or NAME(ARGS)=VALUE", arg),
+    };
+    if arguments.is_empty() && text.is_none() {
+        return Ok((name.to_owned(), None))
+    }
+    Ok((name.to_owned(), Some(Define::new(name.to_owned(), arguments, text))))
+}
+
+/// The modules, interfaces, programs, checkers, primitives and configs a file instantiates
+fn units_used(file: &File) -> impl Iterator<Item=&String> {
+    file.modules_used.iter()
+        .chain(&file.interfaces_used)
+        .chain(&file.programs_used)
+        .chain(&file.checkers_used)
+        .chain(&file.udps_used)
+        .chain(&file.configs_used)
+}
+
+/// Sorts names from hash sets, so verbose output doesn't change between runs
+fn sorted<'a>(names: impl IntoIterator<Item=&'a String>) -> Vec<&'a String> {
+    let mut names: Vec<_> = names.into_iter().collect();
+    names.sort_unstable();
+    names
+}
+
+/// The modules, interfaces, programs, checkers, primitives and configs a file defines
+fn units_defined(file: &File) -> impl Iterator<Item=&String> {
+    file.modules_defined.iter()
+        .chain(&file.interfaces_defined)
+        .chain(&file.programs_defined)
+        .chain(&file.checkers_defined)
+        .chain(&file.udps_defined)
+        .chain(&file.configs_defined)
+}
+
+/// What a file uses a name as, the first kind if it is used as several
+fn use_kind(file: &File, name: &str) -> &'static str {
+    let kinds = [("module", &file.modules_used), ("interface", &file.interfaces_used), ("program", &file.programs_used),
+                 ("checker", &file.checkers_used), ("primitive", &file.udps_used), ("config", &file.configs_used)];
+    kinds.iter().find(|(_, used)| used.contains(name)).map_or("module", |&(kind, _)| kind)
+}
+
+/// The names used by the ordered files that no file defines, sorted and grouped by using file
+fn find_unresolved(files: &[&File], order: &[&File]) -> Vec<(String, Vec<String>)> {
+    let defined: HashSet<&String> = files.iter()
+        .flat_map(|&f| units_defined(f).chain(&f.packages_defined))
+        .collect();
+    let mut unresolved: Vec<_> = order.iter()
+        .map(|&f| {
+            let mut names: Vec<_> = units_used(f).chain(&f.packages_used)
+                .filter(|name| !defined.contains(name))
+                .cloned()
+                .collect();
+            names.sort_unstable();
+            names.dedup();
+            (f.name.to_string_lossy().into_owned(), names)
+        })
+        .filter(|(_, names)| !names.is_empty())
+        .collect();
+    unresolved.sort_unstable();
+    unresolved
+}
+
+/// The files in compilation order, with the files each one depends on
+struct Graph<'f> {
+    order: Vec<&'f File>,
+    /// The files the order was collected from, the tops or the files nothing uses
+    roots: Vec<&'f File>,
+    deps: HashMap<&'f File, HashSet<&'f File>>,
+    /// The names that made a file depend on another, sorted
+    reasons: HashMap<(&'f File, &'f File), Vec<Reason>>,
+    /// The names each file uses that didn't resolve to any file
+    misses: Vec<(&'f File, Reason)>,
+    stats: GraphStats,
+}
+
+/// A name used by a file and defined by another, with what kind of name it is
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Reason {
+    kind: &'static str,
+    name: String,
+}
+
+/// Summary of the dependency graph, for --stats
+struct GraphStats {
+    edges: usize,
+    roots: usize,
+    longest_chain: usize,
+}
+
+/// Resolves the dependencies between files, returns them in compilation order
+/// If tops are given, or inferred with auto_top, only the files they need are returned
+fn order_files<'f>(files: &[&'f File], options: &ParseOptions, duplicates: &DuplicatePolicy,
+                   tops: &[String], auto_top: bool, verbosity: u64) -> Result<Graph<'f>> {
+    let verbose = verbosity > 0;
+    let details = verbosity > 1;
+    let mut module_defs: HashMap<String, (&File, &str)> = HashMap::new();
+    let mut package_defs: HashMap<String, (&File, &str)> = HashMap::new();
+
+    for &file in files {
         for module_def in &file.modules_defined {
-            module_defs.insert(module_def.clone(), file);
+            add_def(&mut module_defs, module_def, file, "module", duplicates)?;
+        }
+        for interface_def in &file.interfaces_defined {
+            add_def(&mut module_defs, interface_def, file, "interface", duplicates)?;
+        }
+        for program_def in &file.programs_defined {
+            add_def(&mut module_defs, program_def, file, "program", duplicates)?;
+        }
+        for checker_def in &file.checkers_defined {
+            add_def(&mut module_defs, checker_def, file, "checker", duplicates)?;
+        }
+        for udp_def in &file.udps_defined {
+            add_def(&mut module_defs, udp_def, file, "primitive", duplicates)?;
+        }
+        for config_def in &file.configs_defined {
+            add_def(&mut module_defs, config_def, file, "config", duplicates)?;
         }
         for package_def in &file.packages_defined {
-            package_defs.insert(package_def.clone(), file);
+            add_def(&mut package_defs, package_def, file, "package/class", duplicates)?;
         }
     }
 
-    let mut file_users: HashMap<&File, HashSet<&File>> = files.iter().map(|f| (f, HashSet::new())).collect();
+    // With --libmap a name may be defined once per library, config rules pick which one a use binds to
+    let mut lib_defs: HashMap<(&str, &str), (&File, &str)> = HashMap::new();
+    let mut rules = ConfigRules::default();
+    for &file in files {
+        if let Some(lib) = &file.work_library {
+            let defined = [(&file.modules_defined, "module"), (&file.interfaces_defined, "interface"),
+                           (&file.programs_defined, "program"), (&file.checkers_defined, "checker"),
+                           (&file.udps_defined, "primitive"), (&file.configs_defined, "config")];
+            for (names, kind) in defined {
+                for name in names {
+                    lib_defs.entry((lib, name)).or_insert((file, kind));
+                }
+            }
+        }
+        if rules.default_liblist.is_empty() {
+            rules.default_liblist = file.config_rules.default_liblist.clone();
+        }
+        for (cell, liblist) in &file.config_rules.cell_liblists {
+            rules.cell_liblists.entry(cell.clone()).or_insert_with(|| liblist.clone());
+        }
+        for (cell, target) in &file.config_rules.cell_uses {
+            rules.cell_uses.entry(cell.clone()).or_insert_with(|| target.clone());
+        }
+    }
+    let bind = |file: &File, name: &str| -> Option<(&'f File, &str)> {
+        if lib_defs.is_empty() {
+            return module_defs.get(name).copied()
+        }
+        if let Some((lib, cell)) = rules.cell_uses.get(name) {
+            let lib = lib.as_deref().or(file.work_library.as_deref())?;
+            return lib_defs.get(&(lib, cell.as_str())).copied()
+        }
+        let liblist = rules.cell_liblists.get(name).unwrap_or(&rules.default_liblist);
+        liblist.iter()
+            // Without a rule, cells come from the library of the file using them first
+            .chain(file.work_library.iter().filter(|_| liblist.is_empty()))
+            .find_map(|lib| lib_defs.get(&(lib.as_str(), name)).copied())
+            .or_else(|| module_defs.get(name).copied())
+    };
+
+    // Gathered in parallel, then merged in the order of the files, so that the files declaring a symbol are always in the same order
+    let declarations = |symbols: fn(&File) -> &HashSet<String>| {
+        let per_file: Vec<Vec<&str>> = files.par_iter().map(|&f| symbols(f).iter().map(String::as_str).collect()).collect();
+        let mut defs: HashMap<&str, Vec<&File>> = HashMap::new();
+        for (&file, symbols) in files.iter().zip(per_file) {
+            for symbol in symbols {
+                defs.entry(symbol).or_default().push(file);
+            }
+        }
+        defs
+    };
+    let symbol_defs = declarations(|f| &f.package_symbols);
+    let unit_symbol_defs = declarations(|f| &f.unit_symbols_defined);
+    // A symbol declared by several packages comes from the one the file imports, else it is a duplicate
+    let package_symbol = |file: &File, symbol: &str| -> Result<Option<&'f File>> {
+        let defs = match symbol_defs.get(symbol) {
+            Some(defs) => defs,
+            None => return Ok(None),
+        };
+        let imported: Vec<&File> = defs.iter().copied()
+            .filter(|dep| dep.packages_defined.iter().any(|p| file.packages_used.contains(p)))
+            .collect();
+        let candidates = if imported.is_empty() { defs } else { &imported };
+        let (first, last) = (candidates[0], candidates[candidates.len() - 1]);
+        if first != last {
+            let (user, first_name, last_name) = (file.name.display(), first.name.display(), last.name.display());
+            match duplicates.on_duplicate {
+                OnDuplicate::Error => bail!("{} uses {}, which is declared in both {} and {}", user, symbol, first_name, last_name),
+                OnDuplicate::Warn => warning!(file: &file.name, "duplicate", "{} uses {}, which is declared in both {} and {}, using {}", user, symbol, first_name, last_name, first_name),
+                OnDuplicate::First => (),
+                OnDuplicate::Last => return Ok(Some(last)),
+            }
+        }
+        Ok(Some(first))
+    };
+    let macro_defs: HashMap<&str, &File> = files.iter()
+        .flat_map(|&f| f.macros_defined.iter().map(move |m| (m.as_str(), f)))
+        .collect();
+
+    let mut file_users: HashMap<&File, HashSet<&File>> = files.iter().map(|&f| (f, HashSet::new())).collect();
     let mut file_deps: HashMap<&File, HashSet<&File>> = HashMap::new();
-    for file in &files {
+    let mut reasons: HashMap<(&File, &File), Vec<Reason>> = HashMap::new();
+    let mut misses = Vec::new();
+    for &file in files {
         let mut deps = HashSet::new();
-        for package_use in &file.packages_used {
-            if let Some(&dep) = package_defs.get(package_use) {
-                if dep == file {
-                    continue
+        let mut add_dep = |dep: &'f File, kind: &'static str, name: &str| {
+            if dep == file {
+                return false
+            }
+            file_users.get_mut(dep).unwrap().insert(file);
+            reasons.entry((file, dep)).or_default().push(Reason { kind, name: name.to_owned() });
+            deps.insert(dep)
+        };
+        for package_use in sorted(&file.packages_used) {
+            if let Some(&(dep, kind)) = package_defs.get(package_use) {
+                if add_dep(dep, kind, package_use) && details {
+                    eprintln!("{} uses {} {} from {}", file.name.to_string_lossy(), kind, package_use, dep.name.to_string_lossy());
                 }
-                file_users.get_mut(dep).unwrap().insert(file);
-                if deps.insert(dep) && verbose {
-                    println!("{} uses a package/class from {}", file.name.to_string_lossy(), dep.name.to_string_lossy());
+            } else {
+                misses.push((file, Reason { kind: "package/class", name: package_use.clone() }));
+            }
+        }
+        for symbol_use in sorted(&file.symbols_used) {
+            if let Some(dep) = if options.deep_resolve { package_symbol(file, symbol_use)? } else { None } {
+                if add_dep(dep, "package symbol", symbol_use) && details {
+                    eprintln!("{} uses package symbol {} from {}", file.name.to_string_lossy(), symbol_use, dep.name.to_string_lossy());
+                }
+            } else if let Some(&dep) = unit_symbol_defs.get(symbol_use.as_str()).and_then(|defs| defs.first()).filter(|_| options.compilation_unit) {
+                if add_dep(dep, "$unit symbol", symbol_use) && details {
+                    eprintln!("{} uses $unit symbol {} from {}", file.name.to_string_lossy(), symbol_use, dep.name.to_string_lossy());
+                }
+            }
+        }
+        for macro_use in sorted(&file.macros_used) {
+            if let Some(&dep) = macro_defs.get(macro_use.as_str()).filter(|_| options.macro_deps) {
+                if add_dep(dep, "macro", macro_use) && details {
+                    eprintln!("{} uses macro {} from {}", file.name.to_string_lossy(), macro_use, dep.name.to_string_lossy());
                 }
             }
         }
-        'module_used_loop: for module_use in &file.modules_used {
-            if let Some(&dep) = module_defs.get(module_use) {
-                if dep == file {
-                    continue
+        for hier_ref in sorted(&file.hier_refs) {
+            if let Some(&(dep, kind)) = module_defs.get(hier_ref) {
+                if add_dep(dep, "hierarchical reference", hier_ref) && details {
+                    eprintln!("{} references the hierarchy of {} {} from {}", file.name.to_string_lossy(), kind, hier_ref, dep.name.to_string_lossy());
                 }
+            }
+        }
+        'module_used_loop: for module_use in sorted(units_used(file)) {
+            if let Some((dep, kind)) = bind(file, module_use) {
                 for dep_package_use in &dep.packages_used {
                     if file.packages_defined.contains(dep_package_use) {
                         // Package use has priority over module use, so don't consider this dep
                         continue 'module_used_loop
                     }
                 }
-                file_users.get_mut(dep).unwrap().insert(file);
-                if deps.insert(dep) && verbose {
-                    println!("{} uses a module from {}", file.name.to_string_lossy(), dep.name.to_string_lossy());
+                if add_dep(dep, kind, module_use) && details {
+                    eprintln!("{} uses {} {} from {}", file.name.to_string_lossy(), kind, module_use, dep.name.to_string_lossy());
+                }
+            } else {
+                if details {
+                    eprintln!("{} uses unresolved {}", file.name.to_string_lossy(), module_use);
+                }
+                misses.push((file, Reason { kind: use_kind(file, module_use), name: module_use.clone() }));
+            }
+        }
+        for (module, port, interface) in &file.interface_connections {
+            let generic = bind(file, module).is_some_and(|(dep, _)| dep.generic_interface_ports.contains(&(module.clone(), port.clone())));
+            if let Some((dep, "interface")) = bind(file, interface).filter(|_| generic) {
+                if add_dep(dep, "interface on generic port", &format!("{}.{}", module, port)) && details {
+                    eprintln!("{} passes interface {} from {} to generic port {}.{}", file.name.to_string_lossy(), interface, dep.name.to_string_lossy(), module, port);
                 }
             }
         }
         file_deps.insert(file, deps);
     }
 
-    let roots = file_users.into_iter().filter_map(|(f, u)| {
-        if u.is_empty() {
-            Some(f)
-        } else {
-            None
+    // Ties are broken by the order the sources were given in
+    let positions: HashMap<&File, usize> = files.iter().enumerate().map(|(i, &f)| (f, i)).collect();
+    let mut tops: Vec<&str> = tops.iter().map(String::as_str).collect();
+    if auto_top {
+        let used: HashSet<&String> = files.iter().flat_map(|&f| units_used(f)).collect();
+        let mut inferred: Vec<_> = module_defs.iter()
+            .filter(|(name, (file, _))| !used.contains(name) && !file.library)
+            .map(|(name, &(file, _))| (positions[file], name.as_str()))
+            .collect();
+        inferred.sort_unstable();
+        for (_, top) in inferred {
+            if verbose {
+                eprintln!("Inferred top {}", top);
+            }
+            tops.push(top);
         }
-    });
-
-    if verbose {
-        print!("Ordered source files: ");
     }
+    // A file whose definitions all lost to files matching an earlier --prefer-file pattern is only compiled if a top needs it
+    let outranked = |file: &File| {
+        let wins = |defs: &HashMap<String, (&File, &str)>, name: &String| defs.get(name)
+            .is_some_and(|&(winner, _)| duplicates.preference(winner) < duplicates.preference(file));
+        let mut defined = units_defined(file).map(|name| wins(&module_defs, name))
+            .chain(file.packages_defined.iter().map(|name| wins(&package_defs, name)))
+            .peekable();
+        defined.peek().is_some() && defined.all(|lost| lost)
+    };
+    let roots: Vec<_> = if tops.is_empty() && !auto_top {
+        let mut roots: Vec<_> = files.iter().copied().filter(|f| file_users[f].is_empty() && !f.library && !outranked(f)).collect();
+        // Files that depend on each other are all used, so a cycle that no root reaches would have no root at all
+        let mut reached: HashSet<&File> = roots.iter().copied().collect();
+        for &root in &roots {
+            collect_deps_recursive(root, &file_deps, &positions, &mut reached, &mut Vec::new());
+        }
+        for &file in files {
+            if !file.library && !outranked(file) && reached.insert(file) {
+                roots.push(file);
+                collect_deps_recursive(file, &file_deps, &positions, &mut reached, &mut Vec::new());
+            }
+        }
+        roots
+    } else {
+        tops.iter()
+            .map(|&top| match module_defs.get(top) {
+                Some(&(file, _)) => Ok(file),
+                None => Err(anyhow!("Top {} is not defined in any source file", top)),
+            })
+            .collect::<Result<_>>()?
+    };
 
+    let mut order = Vec::new();
     let mut visited_files = HashSet::new();
+    let mut visited_roots = Vec::new();
     for root in roots {
-        print_deps_recursive(root, &file_deps, &mut visited_files, absolute);
-        if absolute {
-            print!("{} ", std::fs::canonicalize(&root.name)?.to_string_lossy());
-        } else {
-            print!("{} ", root.name.to_string_lossy());
+        if visited_files.insert(root) {
+            visited_roots.push(root);
+            collect_deps_recursive(root, &file_deps, &positions, &mut visited_files, &mut order);
+            order.push(root);
+        }
+    }
+    if verbose {
+        for file in files.iter().filter(|f| f.library && !visited_files.contains(*f)) {
+            eprintln!("Library file {} is not used", file.name.to_string_lossy());
+        }
+    }
+    if verbose && !tops.is_empty() {
+        for file in files.iter().filter(|f| !f.library && !visited_files.contains(*f)) {
+            eprintln!("{} is not needed by {}", file.name.to_string_lossy(), tops.join(", "));
+        }
+    }
+    let stats = GraphStats {
+        edges: file_deps.values().map(HashSet::len).sum(),
+        roots: visited_roots.len(),
+        longest_chain: longest_chain(&file_deps),
+    };
+    if options.timescale_first {
+        order = float_timescales(order, &file_deps);
+    }
+    for names in reasons.values_mut() {
+        names.sort_unstable();
+        names.dedup();
+    }
+    Ok(Graph { order, roots: visited_roots, deps: file_deps, reasons, misses, stats })
+}
+
+/// The sets of files that depend on each other, with their files sorted by name
+fn find_cycles<'f>(files: &[&'f File], file_deps: &HashMap<&File, HashSet<&'f File>>) -> Vec<Vec<&'f File>> {
+    // Tarjan's algorithm, each strongly connected component with more than one file is a cycle
+    struct Search<'f, 'd> {
+        file_deps: &'d HashMap<&'d File, HashSet<&'f File>>,
+        scope: HashSet<&'f File>,
+        indices: HashMap<&'f File, (usize, usize)>,
+        stack: Vec<&'f File>,
+        cycles: Vec<Vec<&'f File>>,
+    }
+    fn visit<'f>(file: &'f File, search: &mut Search<'f, '_>) -> usize {
+        let index = search.indices.len();
+        search.indices.insert(file, (index, index));
+        search.stack.push(file);
+        let mut low = index;
+        let deps: Vec<&'f File> = search.file_deps[file].iter().copied().filter(|dep| search.scope.contains(dep)).collect();
+        for dep in deps {
+            match search.indices.get(dep) {
+                None => low = low.min(visit(dep, search)),
+                Some(&(dep_index, _)) if search.stack.contains(&dep) => low = low.min(dep_index),
+                _ => (),
+            }
+        }
+        search.indices.insert(file, (index, low));
+        if low == index {
+            let start = search.stack.iter().rposition(|&f| f == file).unwrap();
+            let mut component = search.stack.split_off(start);
+            if component.len() > 1 {
+                component.sort_by(|a, b| a.name.cmp(&b.name));
+                search.cycles.push(component);
+            }
         }
+        low
     }
+    let mut search = Search { file_deps, scope: files.iter().copied().collect(), indices: HashMap::new(), stack: Vec::new(), cycles: Vec::new() };
+    for &file in files {
+        if !search.indices.contains_key(file) {
+            visit(file, &mut search);
+        }
+    }
+    let mut cycles = search.cycles;
+    cycles.sort_by(|a, b| a[0].name.cmp(&b[0].name));
+    cycles
+}
 
+/// Splits the files in groups that only depend on the groups before them, each in the order of the files.
+/// The files of a cycle are all in the same group
+fn compile_groups<'f>(files: &[&'f File], file_deps: &HashMap<&File, HashSet<&'f File>>, cycles: &[Vec<&'f File>]) -> Vec<Vec<&'f File>> {
+    // Kahn's algorithm on the graph where each cycle is a single node, standing for its first file
+    let mut node: HashMap<&File, &File> = files.iter().map(|&file| (file, file)).collect();
+    for cycle in cycles {
+        for &file in cycle {
+            node.insert(file, cycle[0]);
+        }
+    }
+    let node = &node;
+    let edges: HashSet<(&File, &File)> = files.iter()
+        .flat_map(|&file| file_deps[file].iter().filter_map(move |dep| Some((node[file], *node.get(dep)?))))
+        .filter(|(user, dep)| user != dep)
+        .collect();
+    let mut pending: HashMap<&File, usize> = HashMap::new();
+    let mut users: HashMap<&File, Vec<&File>> = HashMap::new();
+    for &(user, dep) in &edges {
+        *pending.entry(user).or_default() += 1;
+        users.entry(dep).or_default().push(user);
+    }
+    let mut levels = HashMap::new();
+    let mut level: Vec<&File> = files.iter().map(|file| node[file]).filter(|file| !pending.contains_key(file)).collect();
+    let mut depth = 0;
+    while !level.is_empty() {
+        let mut next = Vec::new();
+        for file in level {
+            if levels.insert(file, depth).is_some() {
+                continue
+            }
+            for &user in users.get(file).into_iter().flatten() {
+                let count = pending.get_mut(user).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    next.push(user);
+                }
+            }
+        }
+        level = next;
+        depth += 1;
+    }
+    let mut groups = vec![Vec::new(); depth];
+    for &file in files {
+        groups[levels[node[file]]].push(file);
+    }
+    groups.retain(|group| !group.is_empty());
+    groups
+}
+
+/// Number of files in the longest path of dependencies, ignoring the edges closing a cycle
+fn longest_chain(file_deps: &HashMap<&File, HashSet<&File>>) -> usize {
+    fn chain_from<'f>(file: &'f File, file_deps: &HashMap<&File, HashSet<&'f File>>, lengths: &mut HashMap<&'f File, usize>) -> usize {
+        if let Some(&length) = lengths.get(file) {
+            return length
+        }
+        lengths.insert(file, 0);
+        let length = 1 + file_deps[file].iter().map(|dep| chain_from(dep, file_deps, lengths)).max().unwrap_or(0);
+        lengths.insert(file, length);
+        length
+    }
+    let mut lengths = HashMap::new();
+    file_deps.keys().map(|file| chain_from(file, file_deps, &mut lengths)).max().unwrap_or(0)
+}
+
+/// Reorders files so those with a `timescale come as early as their dependencies allow
+fn float_timescales<'f>(order: Vec<&'f File>, file_deps: &HashMap<&File, HashSet<&'f File>>) -> Vec<&'f File> {
+    // Pulling a file with a timescale earlier means pulling its dependencies too
+    let mut wanted = HashSet::new();
+    let mut stack: Vec<_> = order.iter().copied().filter(|f| f.has_timescale).collect();
+    while let Some(file) = stack.pop() {
+        if wanted.insert(file) {
+            stack.extend(&file_deps[file]);
+        }
+    }
+
+    let mut remaining = order;
+    let mut pending: HashSet<&File> = remaining.iter().copied().collect();
+    let mut reordered = Vec::new();
+    while !remaining.is_empty() {
+        let ready = |f: &&File| file_deps[f].iter().all(|d| !pending.contains(d));
+        let next = remaining.iter().position(|f| wanted.contains(f) && ready(f))
+            .or_else(|| remaining.iter().position(ready))
+            .unwrap_or(0); // Only in a cycle, keep the original order there
+        let file = remaining.remove(next);
+        pending.remove(file);
+        reordered.push(file);
+    }
+    reordered
+}
+
+/// Records a definition, a name defined by several files is resolved according to the duplicate policy
+fn add_def<'f>(defs: &mut HashMap<String, (&'f File, &'static str)>, name: &str, file: &'f File,
+               kind: &'static str, duplicates: &DuplicatePolicy) -> Result<()> {
+    if let Some(&(prev, _)) = defs.get(name).filter(|(prev, _)| *prev != file) {
+        if prev.work_library != file.work_library {
+            // Config rules pick between the libraries, the first definition is only a fallback
+            return Ok(())
+        }
+        let (prev_preference, preference) = (duplicates.preference(prev), duplicates.preference(file));
+        if prev_preference < preference {
+            return Ok(())
+        }
+        if prev_preference == preference {
+            match duplicates.on_duplicate {
+                OnDuplicate::Error => bail!("{} is defined in both {} and {}", name, prev.name.display(), file.name.display()),
+                OnDuplicate::Warn => {
+                    warning!(file: &file.name, "duplicate", "{} is defined in both {} and {}, using {}", name, prev.name.display(), file.name.display(), prev.name.display());
+                    return Ok(())
+                }
+                OnDuplicate::First => return Ok(()),
+                OnDuplicate::Last => (),
+            }
+        }
+    }
+    defs.insert(name.to_owned(), (file, kind));
     Ok(())
 }
 
-fn print_deps_recursive<'f>(file: &File, file_deps: &HashMap<&File, HashSet<&'f File>>,
-                        visited_files: &mut HashSet<&'f File>, absolute: bool) {
-    let deps = file_deps.get(file).unwrap();
-    for &dep in deps {
+fn collect_deps_recursive<'f>(file: &File, file_deps: &HashMap<&File, HashSet<&'f File>>, positions: &HashMap<&File, usize>,
+                              visited_files: &mut HashSet<&'f File>, order: &mut Vec<&'f File>) {
+    let mut deps: Vec<_> = file_deps.get(file).unwrap().iter().copied().collect();
+    deps.sort_by_key(|d| positions[d]);
+    for dep in deps {
         if visited_files.insert(dep) {
-            print_deps_recursive(dep, file_deps, visited_files, absolute);
-            if absolute {
-                print!("{} ", std::fs::canonicalize(&dep.name).unwrap().to_string_lossy());
-            } else {
-                print!("{} ", dep.name.to_string_lossy());
-            }
+            collect_deps_recursive(dep, file_deps, positions, visited_files, order);
+            order.push(dep);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_write_keeps_the_old_output() {
+        let dir = std::env::temp_dir().join(format!("sv_auto_order-write-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("order.f");
+        std::fs::write(&path, "old.sv\n").unwrap();
+
+        let result = write_atomic_with(&path, |file| {
+            std::io::Write::write_all(file, b"new")?;
+            Err(std::io::Error::other("injected"))
+        });
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old.sv\n");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1, "the temporary file is left behind");
+
+        write_atomic(&path, "new.sv\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new.sv\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+/// How paths are quoted in the output, set by --quote
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Quote {
+    /// Only when the path needs it, in the style of the output
+    Auto,
+    /// Every path, in the style of the output
+    Always,
+    Never,
+    /// POSIX shell quoting when needed, whatever the output
+    Shell,
+    /// Tcl braces when needed, whatever the output
+    Tcl,
+}
+
+impl Quote {
+    /// Formats a path for a filelist like the -f files of the simulators
+    pub fn filelist(self, path: &str) -> String {
+        match self {
+            Quote::Never => path.to_owned(),
+            Quote::Shell => shell(path),
+            Quote::Tcl => tcl(path),
+            Quote::Always => double_quoted(path),
+            Quote::Auto if path.contains(|c: char| c.is_whitespace() || "\"$#".contains(c)) || path.contains("//") => double_quoted(path),
+            Quote::Auto => path.to_owned(),
+        }
+    }
+
+    /// Formats a path of the plain list, whose separators already delimit the paths, so auto never quotes
+    pub fn list(self, path: &str) -> String {
+        match self {
+            Quote::Auto => path.to_owned(),
+            quote => quote.filelist(path),
+        }
+    }
+}
+
+/// Double quotes for filelists, escaping quotes and backslashes, with $$ for a literal $
+fn double_quoted(path: &str) -> String {
+    let escaped = path.replace('\\', "\\\\").replace('"', "\\\"").replace('$', "$$");
+    format!("\"{}\"", escaped)
+}
+
+/// Single quotes a path for a POSIX shell if it has special characters
+pub fn shell(path: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-./+,:@%=".contains(c);
+    if !path.is_empty() && path.chars().all(safe) {
+        return path.to_owned()
+    }
+    // A single quote can't be escaped inside single quotes, so close them around an escaped one
+    format!("'{}'", path.replace('\'', "'\\''"))
+}
+
+/// Makes a path a single Tcl word, in braces if possible
+pub fn tcl(path: &str) -> String {
+    let special = |c: char| c.is_whitespace() || "{}[]$\"\\;".contains(c);
+    if !path.is_empty() && !path.contains(special) {
+        return path.to_owned()
+    }
+    // Braces only work if the ones in the path are balanced and none is escaped
+    let mut depth = 0i32;
+    let balanced = path.chars().all(|c| {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => (),
+        }
+        depth >= 0
+    }) && depth == 0;
+    if balanced && !path.contains('\\') {
+        return format!("{{{}}}", path)
+    }
+    path.chars().map(|c| if special(c) { format!("\\{}", c) } else { c.to_string() }).collect()
+}
+
+/// Escapes a path in a Makefile rule or variable, so it stays one word
+pub fn make(path: &str) -> String {
+    let mut escaped = String::new();
+    for c in path.chars() {
+        match c {
+            '$' => escaped.push_str("$$"),
+            ' ' | '#' | ':' | '%' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes a path in a Ninja build statement
+pub fn ninja(path: &str) -> String {
+    path.replace('$', "$$").replace(' ', "$ ").replace(':', "$:")
+}
+
+/// Leaves YAML scalars plain when they can't be read as anything else, or puts them in double quotes.
+/// Anything YAML 1.1 or 1.2 readers take for a boolean, null or number is quoted
+pub fn yaml(text: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_./+".contains(c);
+    let lower = text.to_ascii_lowercase();
+    let special = ["true", "false", "yes", "no", "y", "n", "on", "off", "null", "~", ".inf", "+.inf", ".nan"].contains(&lower.as_str());
+    // YAML 1.1 numbers may have a sign, underscores, and 0x, 0o or 0b prefixes
+    let digits = lower.trim_start_matches('+').replace('_', "");
+    let prefixed = ["0x", "0o", "0b"].iter().any(|prefix| digits.strip_prefix(prefix).is_some_and(|rest| !rest.is_empty()));
+    let number = prefixed || digits.parse::<f64>().is_ok();
+    if !text.is_empty() && text.chars().all(plain) && !special && !number {
+        return text.to_owned()
+    }
+    // JSON strings are valid double quoted YAML
+    serde_json::to_string(text).unwrap()
+}
+
+/// Quotes a CMake path argument, with forward slashes since CMake takes backslashes as escapes
+pub fn cmake(path: &str) -> String {
+    cmake_list(&[path])
+}
+
+/// Quotes a CMake list of paths as a single argument
+pub fn cmake_list(items: &[&str]) -> String {
+    let escaped: Vec<_> = items.iter().map(|item| cmake_escape(&item.replace('\\', "/"))).collect();
+    format!("\"{}\"", escaped.join(";"))
+}
+
+/// Quotes a CMake argument that isn't a path, keeping its backslashes
+pub fn cmake_value(value: &str) -> String {
+    format!("\"{}\"", cmake_escape(value))
+}
+
+fn cmake_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('$', "\\$").replace(';', "\\;")
+}
+
+/// Quotes a CSV field as RFC 4180 does, if it has a comma, quote or line break
+pub fn csv(field: &str) -> String {
+    if field.contains(|c| ",\"\r\n".contains(c)) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
@@ -0,0 +1,220 @@
+mod common;
+
+use common::{fixture, order, run, stderr};
+
+const EXPORT_CHAIN: &[&str] = &["user.sv", "umbrella_pkg.sv", "axi_pkg.sv"];
+
+#[test]
+fn single_job_same_as_default() {
+    let jobs = [&["-j", "1"], EXPORT_CHAIN].concat();
+    assert_eq!(order("export_chain", &jobs), order("export_chain", EXPORT_CHAIN));
+}
+
+#[test]
+fn single_job_verbose_output_is_deterministic() {
+    let args = [&["-j", "1", "-vv"], EXPORT_CHAIN].concat();
+    let first = run(&fixture("export_chain"), &args);
+    assert!(first.status.success(), "{}", stderr(&first));
+    for _ in 0..5 {
+        let again = run(&fixture("export_chain"), &args);
+        assert_eq!(again.stdout, first.stdout);
+        assert_eq!(again.stderr, first.stderr);
+    }
+    assert!(stderr(&first).starts_with("Parsing user.sv\nParsing umbrella_pkg.sv\nParsing axi_pkg.sv\n"), "{}", stderr(&first));
+}
+
+#[test]
+fn only_the_order_on_stdout() {
+    let output = run(&fixture("unresolved"), &["-vv", "--unresolved", "warn", "top.sv"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "top.sv\n");
+    let stderr = stderr(&output);
+    assert!(stderr.contains("Parsing top.sv\n"), "{}", stderr);
+    assert!(stderr.contains("top.sv uses unresolved missing_cell\n"), "{}", stderr);
+    assert!(stderr.contains("Warning: top.sv: unresolved missing_cell\n"), "{}", stderr);
+}
+
+#[test]
+fn quiet_drops_the_warnings() {
+    let output = run(&fixture("unresolved"), &["--quiet", "--unresolved", "warn", "top.sv"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "top.sv\n");
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn output_file_leaves_stdout_empty() {
+    let dir = common::temp_dir("output-file");
+    let path = dir.join("order.f");
+    let output = run(&fixture("export_chain"), &[&["-v", "-o", path.to_str().unwrap()], EXPORT_CHAIN].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "axi_pkg.sv\numbrella_pkg.sv\nuser.sv\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+const SPACED: &[&str] = &["top.sv", "IP Cores/uart.sv"];
+
+#[test]
+fn spaced_paths_one_per_line() {
+    assert_eq!(order("spaced", SPACED), "IP Cores/uart.sv\ntop.sv\n");
+}
+
+#[test]
+fn spaced_paths_with_a_separator() {
+    assert_eq!(order("spaced", &[&["--separator", ", "], SPACED].concat()), "IP Cores/uart.sv, top.sv");
+}
+
+#[test]
+fn spaced_paths_terminated_by_nul() {
+    assert_eq!(order("spaced", &[&["-0"], SPACED].concat()), "IP Cores/uart.sv\0top.sv\0");
+    assert_eq!(order("spaced", &[&["--null"], SPACED].concat()), "IP Cores/uart.sv\0top.sv\0");
+}
+
+#[test]
+fn spaced_paths_followed_by_spaces() {
+    // The old output, where only --quote makes the paths apart again
+    assert_eq!(order("spaced", &[&["--space"], SPACED].concat()), "IP Cores/uart.sv top.sv ");
+    assert_eq!(order("spaced", &[&["--space", "--quote", "shell"], SPACED].concat()), "'IP Cores/uart.sv' top.sv ");
+}
+
+#[test]
+fn relative_to_a_directory_not_above_every_source() {
+    // core.sv is given absolute, top.sv is outside the directory
+    let core = fixture("glob").join("rtl/core/core.sv");
+    let order = order("glob", &["--relative-to", "rtl/core", "rtl/top.sv", core.to_str().unwrap(), "rtl/core/pkg/types_pkg.sv"]);
+    assert_eq!(order, "pkg/types_pkg.sv\ncore.sv\n../top.sv\n");
+    let output = run(&fixture("glob"), &["--relative-to", "rtl/core", "--absolute", "rtl/top.sv"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn graph_command_formats() {
+    assert_eq!(order("export_chain", &[&["graph"], EXPORT_CHAIN].concat()), "\
+digraph dependencies {
+    node [shape=box];
+    \"axi_pkg.sv\";
+    \"umbrella_pkg.sv\";
+    \"user.sv\" [style=bold];
+    \"umbrella_pkg.sv\" -> \"axi_pkg.sv\";
+    \"user.sv\" -> \"umbrella_pkg.sv\";
+}
+");
+    let report: serde_json::Value = serde_json::from_str(&order("export_chain", &[&["graph", "--format", "json"], EXPORT_CHAIN].concat())).unwrap();
+    assert_eq!(report["order"], serde_json::json!(["axi_pkg.sv", "umbrella_pkg.sv", "user.sv"]));
+    assert_eq!(report["files"][2]["dependencies"], serde_json::json!(["umbrella_pkg.sv"]));
+}
+
+#[test]
+fn graph_command_edge_labels() {
+    let graph = order("export_chain", &[&["graph", "--edge-labels"], EXPORT_CHAIN].concat());
+    assert!(graph.contains("    \"user.sv\" -> \"umbrella_pkg.sv\" [label=\"package/class umbrella_pkg\"];\n"), "{}", graph);
+    assert_eq!(graph, order("export_chain", &[&["--format", "dot", "--edge-labels"], EXPORT_CHAIN].concat()));
+}
+
+#[test]
+fn dot_roots_and_labels_in_sorted_order() {
+    let graph = order("multi_edge", &["--format", "dot", "--edge-labels", "top.sv", "monitor.sv", "leaf.sv", "bus.sv"]);
+    assert_eq!(graph, "\
+digraph dependencies {
+    node [shape=box];
+    \"bus.sv\";
+    \"leaf.sv\";
+    \"monitor.sv\" [style=bold];
+    \"top.sv\" [style=bold];
+    \"leaf.sv\" -> \"bus.sv\" [label=\"interface bus_if\"];
+    \"monitor.sv\" -> \"bus.sv\" [label=\"interface bus_if\"];
+    \"top.sv\" -> \"bus.sv\" [label=\"interface bus_if\\npackage/class bus_pkg\"];
+    \"top.sv\" -> \"leaf.sv\" [label=\"module leaf\"];
+}
+");
+    // Whatever the order of the inputs
+    assert_eq!(graph, order("multi_edge", &["--format", "dot", "--edge-labels", "bus.sv", "leaf.sv", "monitor.sv", "top.sv"]));
+}
+
+#[test]
+fn query_command_answers() {
+    let query = |args: &[&str]| run(&fixture("report"), &[&["query"], args, &["top.sv", "leaf.sv", "bus_pkg.sv"]].concat());
+    let output = query(&["find-module", "leaf"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "leaf.sv\n");
+    assert_eq!(String::from_utf8_lossy(&query(&["deps-of", "top.sv"]).stdout), "bus_pkg.sv\nleaf.sv\n");
+    assert_eq!(String::from_utf8_lossy(&query(&["users-of", "bus_pkg"]).stdout), "leaf.sv\ntop.sv\n");
+    let output = query(&["find-module", "nothing"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert_eq!(stderr(&output), "Error: nothing is not defined by any source\n");
+}
+
+#[test]
+fn check_command_finds_misordered_files() {
+    let dir = common::temp_dir("check");
+    let good = dir.join("good.f");
+    std::fs::write(&good, "bus_pkg.sv\nleaf.sv\ntop.sv\n").unwrap();
+    let output = run(&fixture("report"), &["check", good.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+    let bad = dir.join("bad.f");
+    std::fs::write(&bad, "top.sv\nleaf.sv\nbus_pkg.sv\n").unwrap();
+    let output = run(&fixture("report"), &["check", bad.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), format!("\
+Error: leaf.sv is listed before bus_pkg.sv, which it depends on
+Error: top.sv is listed before bus_pkg.sv, which it depends on
+Error: top.sv is listed before leaf.sv, which it depends on
+Error: {} has 3 ordering problems
+", bad.display()));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// The --error-format json lines of a run
+fn json_diagnostics(output: &std::process::Output) -> Vec<serde_json::Value> {
+    stderr(output).lines().map(|line| serde_json::from_str(line).unwrap_or_else(|err| panic!("{}: {}", err, line))).collect()
+}
+
+#[test]
+fn json_diagnostic_for_syntax_error() {
+    let output = run(&fixture("syntax_error"), &["--error-format", "json", "--strict-parse", "broken.sv"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let diagnostics = json_diagnostics(&output);
+    assert_eq!(diagnostics.len(), 1);
+    let diagnostic = diagnostics[0].as_object().unwrap();
+    let keys: Vec<_> = diagnostic.keys().map(String::as_str).collect();
+    assert_eq!(keys, ["category", "column", "file", "line", "message", "severity"]);
+    assert_eq!(diagnostic["severity"], "error");
+    assert_eq!(diagnostic["category"], "parse");
+    assert_eq!(diagnostic["file"], "broken.sv");
+    assert_eq!(diagnostic["line"], 1);
+    assert_eq!(diagnostic["column"], 15);
+    assert!(diagnostic["message"].as_str().unwrap().contains("Syntax error at broken.sv:1:15"));
+}
+
+#[test]
+fn json_diagnostic_for_unresolved_module() {
+    let output = run(&fixture("unresolved"), &["--error-format", "json", "--unresolved", "warn", "top.sv"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(json_diagnostics(&output), [serde_json::json!({
+        "severity": "warning",
+        "file": "top.sv",
+        "line": null,
+        "column": null,
+        "message": "top.sv: unresolved missing_cell",
+        "category": "unresolved",
+    })]);
+}
+
+#[test]
+fn macro_instances_are_diagnostics() {
+    let args = ["--macro-instances", "top.sv", "leaf.sv"];
+    let output = run(&fixture("macro_instance"), &[&["--error-format", "json"], &args[..]].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "leaf.sv\ntop.sv\n");
+    let diagnostics = json_diagnostics(&output);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["category"], "macro");
+    assert_eq!(diagnostics[0]["message"], "top.sv: instantiates leaf through macro LEAF");
+
+    let quiet = run(&fixture("macro_instance"), &[&["--quiet"], &args[..]].concat());
+    assert_eq!(stderr(&quiet), "");
+}
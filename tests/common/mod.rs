@@ -0,0 +1,44 @@
+//! Runs the binary on the fixtures, from their directory so the paths in the output are short
+#![allow(dead_code)]
+
+pub mod yaml;
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+pub fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+pub fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_sv_auto_order"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+/// The stdout of a run in a fixture that must succeed
+pub fn order(name: &str, args: &[&str]) -> String {
+    let output = run(&fixture(name), args);
+    assert!(output.status.success(), "sv_auto_order {} failed:\n{}", args.join(" "), String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// An empty directory of its own for a test to write in
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sv_auto_order-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The paths ordered by a run in a fixture that must succeed, however they are separated
+pub fn sources(name: &str, args: &[&str]) -> Vec<String> {
+    order(name, args).split_whitespace().map(str::to_owned).collect()
+}
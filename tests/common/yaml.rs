@@ -0,0 +1,159 @@
+//! Reads back the block style YAML the formats write, resolving plain scalars as YAML 1.1 readers do,
+//! so a scalar that should have been quoted comes back as something else than a string
+use serde_json::{Map, Value};
+
+pub fn parse(text: &str) -> Value {
+    let mut lines: Vec<(usize, String)> = text.lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|line| (line.len() - line.trim_start().len(), line.trim().to_owned()))
+        .collect();
+    let mut i = 0;
+    let value = node(&mut lines, &mut i, 0);
+    assert_eq!(i, lines.len(), "unexpected line {:?}", lines.get(i));
+    value
+}
+
+fn node(lines: &mut [(usize, String)], i: &mut usize, indent: usize) -> Value {
+    if lines[*i].1 == "-" || lines[*i].1.starts_with("- ") {
+        let mut items = Vec::new();
+        while *i < lines.len() && lines[*i].0 == indent && (lines[*i].1 == "-" || lines[*i].1.starts_with("- ")) {
+            let rest = lines[*i].1[1..].trim_start().to_owned();
+            if rest.is_empty() {
+                *i += 1;
+                items.push(nested(lines, i, indent));
+            } else {
+                // The item starts on the line of its dash, as if it were indented past it
+                lines[*i] = (indent + 2, rest);
+                items.push(node(lines, i, indent + 2));
+            }
+        }
+        return Value::Array(items)
+    }
+    if !is_mapping_entry(&lines[*i].1) {
+        let value = scalar(&lines[*i].1);
+        *i += 1;
+        return value
+    }
+    let mut map = Map::new();
+    while *i < lines.len() && lines[*i].0 == indent && is_mapping_entry(&lines[*i].1) {
+        let (key, rest) = split_key(&lines[*i].1);
+        *i += 1;
+        let value = if rest.is_empty() { nested(lines, i, indent) } else { scalar(&rest) };
+        assert!(map.insert(key.clone(), value).is_none(), "duplicate key {}", key);
+    }
+    Value::Object(map)
+}
+
+/// The block under a key or dash with nothing after it, sequences may be at the same indent as a key
+fn nested(lines: &mut [(usize, String)], i: &mut usize, indent: usize) -> Value {
+    match lines.get(*i) {
+        Some((next, text)) if *next > indent || (*next == indent && text.starts_with('-')) => {
+            let next = *next;
+            node(lines, i, next)
+        }
+        _ => Value::Null,
+    }
+}
+
+fn is_mapping_entry(line: &str) -> bool {
+    if line.starts_with('"') {
+        let (_, rest) = quoted(line);
+        return rest.starts_with(':')
+    }
+    line.ends_with(':') || line.contains(": ")
+}
+
+fn split_key(line: &str) -> (String, String) {
+    let (key, rest) = if line.starts_with('"') {
+        let (_, rest) = quoted(line);
+        let key = &line[..line.len() - rest.len()];
+        (serde_json::from_str::<String>(key).unwrap(), rest)
+    } else {
+        let end = line.find(": ").unwrap_or(line.len() - 1);
+        match scalar(&line[..end]) {
+            Value::String(key) => (key, &line[end..]),
+            key => panic!("the key of {} reads as {}", line, key),
+        }
+    };
+    let rest = rest.strip_prefix(':').unwrap_or_else(|| panic!("no : after the key in {}", line));
+    (key, rest.trim().to_owned())
+}
+
+/// Splits the items of a flow sequence or mapping, which the formats only write with scalars
+fn flow_items(text: &str) -> impl Iterator<Item=&str> {
+    let mut items = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let end = if rest.starts_with('"') {
+            let (string, after) = quoted(rest);
+            string.len() + after.find(',').unwrap_or(after.len())
+        } else {
+            rest.find(',').unwrap_or(rest.len())
+        };
+        items.push(rest[..end].trim());
+        rest = rest[end..].trim_start_matches(',').trim_start();
+    }
+    items.into_iter()
+}
+
+/// Splits a double quoted string from what follows it
+fn quoted(text: &str) -> (&str, &str) {
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(1) {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return (&text[..=i], &text[i + 1..]),
+            _ => escaped = false,
+        }
+    }
+    panic!("unterminated string {}", text)
+}
+
+fn scalar(text: &str) -> Value {
+    if text.starts_with('"') {
+        return serde_json::from_str(text).unwrap_or_else(|err| panic!("{}: {}", err, text))
+    }
+    assert!(!text.starts_with('\''), "single quoted scalars aren't written: {}", text);
+    if let Some(items) = text.strip_prefix('[') {
+        let items = items.strip_suffix(']').unwrap_or_else(|| panic!("unterminated sequence {}", text));
+        return Value::Array(flow_items(items).map(scalar).collect())
+    }
+    if let Some(entries) = text.strip_prefix('{') {
+        let entries = entries.strip_suffix('}').unwrap_or_else(|| panic!("unterminated mapping {}", text));
+        return Value::Object(flow_items(entries).map(split_key).map(|(key, value)| (key, scalar(&value))).collect())
+    }
+    let lower = text.to_ascii_lowercase();
+    if ["null", "~"].contains(&lower.as_str()) {
+        return Value::Null
+    }
+    if ["true", "yes", "on", "y"].contains(&lower.as_str()) {
+        return Value::Bool(true)
+    }
+    if ["false", "no", "off", "n"].contains(&lower.as_str()) {
+        return Value::Bool(false)
+    }
+    let digits = text.trim_start_matches(['+', '-']).replace('_', "");
+    let radix = [("0x", 16), ("0o", 8), ("0b", 2)].iter().find(|(prefix, _)| digits.starts_with(prefix));
+    if let Some(&(prefix, radix)) = radix {
+        if let Ok(number) = i64::from_str_radix(&digits[prefix.len()..], radix) {
+            return Value::from(if text.starts_with('-') { -number } else { number })
+        }
+    }
+    if digits.len() > 1 && digits.starts_with('0') && digits.chars().all(|c| c.is_ascii_digit()) {
+        // YAML 1.1 octal
+        if let Ok(number) = i64::from_str_radix(&digits[1..], 8) {
+            return Value::from(if text.starts_with('-') { -number } else { number })
+        }
+    }
+    if let Ok(number) = text.replace('_', "").parse::<i64>() {
+        return Value::from(number)
+    }
+    // JSON has no infinity or NaN to return
+    assert!(![".inf", "+.inf", "-.inf", ".nan"].contains(&lower.as_str()), "{} reads as a float", text);
+    if let Ok(number) = text.replace('_', "").parse::<f64>() {
+        if text.chars().any(|c| c.is_ascii_digit()) {
+            return Value::from(number)
+        }
+    }
+    Value::String(text.to_owned())
+}
@@ -0,0 +1 @@
+module leaf; endmodule
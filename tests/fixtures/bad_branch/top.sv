@@ -0,0 +1,7 @@
+module top;
+`ifdef SIM
+    `NOT_DEFINED_ANYWHERE
+`else
+    leaf l();
+`endif
+endmodule
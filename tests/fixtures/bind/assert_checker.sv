@@ -0,0 +1,3 @@
+module assert_checker (input logic clk, input logic valid);
+  assert property (@(posedge clk) valid |=> !valid);
+endmodule
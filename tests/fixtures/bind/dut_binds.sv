@@ -0,0 +1 @@
+bind dut_core assert_checker u_chk (.clk(clk), .valid(valid));
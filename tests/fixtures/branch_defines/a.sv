@@ -0,0 +1,4 @@
+`ifdef FOO
+`define BAR 1
+`endif
+module a; endmodule
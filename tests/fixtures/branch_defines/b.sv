@@ -0,0 +1,5 @@
+module b;
+`ifdef BAR
+    a u();
+`endif
+endmodule
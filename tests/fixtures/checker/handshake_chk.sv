@@ -0,0 +1,3 @@
+checker handshake_chk (logic clk, logic req, logic ack);
+  assert property (@(posedge clk) req |-> ##[1:3] ack);
+endchecker
@@ -0,0 +1,5 @@
+config cfg;
+  design rtl.top;
+  default liblist rtl;
+  cell sram use rtl.sram_fast;
+endconfig
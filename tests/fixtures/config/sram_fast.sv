@@ -0,0 +1,2 @@
+module sram_fast;
+endmodule
@@ -0,0 +1,3 @@
+module top;
+  sram u_mem ();
+endmodule
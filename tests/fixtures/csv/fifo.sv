@@ -0,0 +1,3 @@
+module fifo;
+  logic [cfg_pkg::DEPTH-1:0] ptr;
+endmodule
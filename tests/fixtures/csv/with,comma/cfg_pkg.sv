@@ -0,0 +1,3 @@
+package cfg_pkg;
+  parameter int DEPTH = 4;
+endpackage
@@ -0,0 +1,5 @@
+module ping #(parameter int N = 1);
+    if (N > 0) begin : g
+        pong #(.N(N - 1)) u();
+    end
+endmodule
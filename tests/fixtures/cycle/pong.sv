@@ -0,0 +1,5 @@
+module pong #(parameter int N = 1);
+    if (N > 0) begin : g
+        ping #(.N(N - 1)) u();
+    end
+endmodule
@@ -0,0 +1,3 @@
+package cfg_pkg;
+    parameter int NUM_CORES = 4;
+endpackage
@@ -0,0 +1,4 @@
+module core;
+    import other_pkg::*;
+    logic [NUM_CORES-1:0] enable;
+endmodule
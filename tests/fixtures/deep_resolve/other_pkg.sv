@@ -0,0 +1,3 @@
+package other_pkg;
+    parameter int NUM_CORES = 2;
+endpackage
@@ -0,0 +1,4 @@
+// cfg_pkg is imported in the compilation unit by an earlier file
+module soc;
+    logic [NUM_CORES-1:0] enable;
+endmodule
@@ -0,0 +1,3 @@
+package base_pkg;
+  typedef logic [7:0] word_t;
+endpackage
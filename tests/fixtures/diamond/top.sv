@@ -0,0 +1,5 @@
+module top;
+  logic [7:0] data;
+  left u_left (.data(data));
+  right u_right (.data(data));
+endmodule
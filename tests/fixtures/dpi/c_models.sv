@@ -0,0 +1,14 @@
+module c_models;
+  import util_pkg::*;
+  import "DPI-C" function int c_model(input int x);
+  import "DPI-C" context task c_wait(input int cycles);
+  import "DPI-C" pure function real c_sqrt(input real x);
+  export "DPI-C" function sv_callback;
+  export "DPI-C" task sv_tick;
+
+  function void sv_callback();
+  endfunction
+
+  task sv_tick();
+  endtask
+endmodule
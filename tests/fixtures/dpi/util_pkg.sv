@@ -0,0 +1,3 @@
+package util_pkg;
+  localparam int LANES = 4;
+endpackage
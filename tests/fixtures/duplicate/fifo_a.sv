@@ -0,0 +1,2 @@
+module fifo #(parameter int DEPTH = 4);
+endmodule
@@ -0,0 +1,3 @@
+module top;
+  fifo u_fifo ();
+endmodule
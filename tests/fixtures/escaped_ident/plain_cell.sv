@@ -0,0 +1,2 @@
+module \plain_cell ;
+endmodule
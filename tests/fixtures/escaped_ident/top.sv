@@ -0,0 +1,5 @@
+module top;
+  \my-weird.name  u_weird ();
+  plain_cell u_plain ();
+  \fifo  u_fifo ();
+endmodule
@@ -0,0 +1,2 @@
+module \my-weird.name ;
+endmodule
@@ -0,0 +1,11 @@
+module exotic #(parameter int N = 4) (input logic [N-1:0] a, output logic [N-1:0] y, output logic z);
+  leaf_cell u_array [N-1:0] (.a(a), .y(y));
+  for (genvar i = 0; i < N; i++) begin : g_cells
+    leaf_cell u_gen (.a(a[i]), .y());
+  end
+  and g_and [N-1:0] (y, a, a);
+  nand #(1) (z, a[0], a[1]);
+  pulldown (weak0) pd (z);
+endmodule
+
+bind exotic: u_array, g_cells[0].u_gen leaf_cell u_bound (.a(a[0]), .y());
@@ -0,0 +1,3 @@
+module leaf_cell (input logic a, output logic y);
+  assign y = a;
+endmodule
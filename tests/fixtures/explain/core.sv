@@ -0,0 +1,3 @@
+module core;
+  alu u_alu (.a(16'h0));
+endmodule
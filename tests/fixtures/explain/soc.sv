@@ -0,0 +1,3 @@
+module soc;
+  core u_core ();
+endmodule
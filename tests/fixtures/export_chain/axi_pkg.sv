@@ -0,0 +1,3 @@
+package axi_pkg;
+    typedef logic [31:0] addr_t;
+endpackage
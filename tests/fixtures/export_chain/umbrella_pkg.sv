@@ -0,0 +1,3 @@
+package umbrella_pkg;
+    export axi_pkg::*;
+endpackage
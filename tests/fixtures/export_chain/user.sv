@@ -0,0 +1,3 @@
+module user;
+    import umbrella_pkg::*;
+endmodule
@@ -0,0 +1,4 @@
+class base_driver;
+    virtual function void drive();
+    endfunction
+endclass
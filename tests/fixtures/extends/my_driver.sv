@@ -0,0 +1,4 @@
+class my_driver extends base_driver;
+    function void drive();
+    endfunction
+endclass
@@ -0,0 +1,3 @@
+class my_txn;
+  rand bit [7:0] data;
+endclass
@@ -0,0 +1,5 @@
+typedef class my_txn;
+
+class txn_queue;
+  my_txn items[$];
+endclass
@@ -0,0 +1,3 @@
+interface axi_if;
+    logic valid;
+endinterface
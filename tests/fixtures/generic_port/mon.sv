@@ -0,0 +1,2 @@
+module mon(interface bus);
+endmodule
@@ -0,0 +1,4 @@
+module top;
+    axi_if bus_i();
+    mon m(.bus(bus_i));
+endmodule
@@ -0,0 +1,3 @@
+module core;
+  types_pkg::word_t data;
+endmodule
@@ -0,0 +1,3 @@
+package types_pkg;
+  typedef logic [15:0] word_t;
+endpackage
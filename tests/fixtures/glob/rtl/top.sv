@@ -0,0 +1,3 @@
+module top;
+  core u_core ();
+endmodule
@@ -0,0 +1,4 @@
+module top;
+  import my_pkg::*;
+  logic [WIDTH-1:0] data;
+endmodule
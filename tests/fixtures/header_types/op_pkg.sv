@@ -0,0 +1,4 @@
+package op_pkg;
+    typedef enum logic [1:0] { ADD, SUB } op_t;
+    localparam int W = 8;
+endpackage
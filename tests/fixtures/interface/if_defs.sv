@@ -0,0 +1,4 @@
+interface axi_if;
+    logic valid;
+    logic ready;
+endinterface
@@ -0,0 +1,3 @@
+module top;
+    axi_if bus();
+endmodule
@@ -0,0 +1,3 @@
+interface class comparable;
+  pure virtual function bit equals(comparable other);
+endclass
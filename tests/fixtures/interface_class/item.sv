@@ -0,0 +1,2 @@
+class item implements comparable;
+endclass
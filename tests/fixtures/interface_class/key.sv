@@ -0,0 +1,2 @@
+class key implements sort_pkg::ordered;
+endclass
@@ -0,0 +1,5 @@
+package sort_pkg;
+  interface class ordered;
+    pure virtual function bit less(ordered other);
+  endclass
+endpackage
@@ -0,0 +1,3 @@
+module bad_a;
+  logic [3:0 x;
+endmodule
@@ -0,0 +1,2 @@
+module bad_b (input logic clk
+endmodule
@@ -0,0 +1,3 @@
+module top;
+  leaf u_leaf ();
+endmodule
@@ -0,0 +1,4 @@
+module top;
+  logic a, y;
+  gate_cell u_cell (.a(a), .y(y));
+endmodule
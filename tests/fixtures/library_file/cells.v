@@ -0,0 +1 @@
+module cell_a; endmodule
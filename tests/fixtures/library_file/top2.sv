@@ -0,0 +1 @@
+module top2; cell_a c(); endmodule
@@ -0,0 +1,2 @@
+top.sv
+-v cells.v
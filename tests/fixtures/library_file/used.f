@@ -0,0 +1,2 @@
+top2.sv
+-v cells.v
@@ -0,0 +1,3 @@
+module user;
+    logic [`WIDTH-1:0] data;
+endmodule
@@ -0,0 +1,5 @@
+`define LEAF(name) leaf name();
+
+module top;
+  `LEAF(u_leaf)
+endmodule
@@ -0,0 +1,5 @@
+interface axi_if;
+  logic valid;
+  logic ready;
+  modport slave (input valid, output ready);
+endinterface
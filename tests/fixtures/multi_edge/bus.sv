@@ -0,0 +1,7 @@
+package bus_pkg;
+  typedef logic [7:0] word_t;
+endpackage
+
+interface bus_if;
+  bus_pkg::word_t data;
+endinterface
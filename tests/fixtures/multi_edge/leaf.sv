@@ -0,0 +1,2 @@
+module leaf (bus_if bus);
+endmodule
@@ -0,0 +1,3 @@
+module monitor;
+  bus_if bus ();
+endmodule
@@ -0,0 +1,5 @@
+module top;
+  bus_pkg::word_t data;
+  bus_if bus ();
+  leaf u_leaf (.bus(bus));
+endmodule
@@ -0,0 +1 @@
+-F ip/soc_loop.f
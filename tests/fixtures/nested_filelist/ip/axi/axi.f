@@ -0,0 +1,2 @@
+axi_xbar.sv
+axi_pkg.sv
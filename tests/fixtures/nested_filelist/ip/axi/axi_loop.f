@@ -0,0 +1,2 @@
+axi_pkg.sv
+-F ../../cycle.f
@@ -0,0 +1,2 @@
+soc.sv
+-F axi/axi.f
@@ -0,0 +1,3 @@
+// Each level is relative to its own filelist
+top.sv
+-F ip/soc.f
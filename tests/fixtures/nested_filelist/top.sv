@@ -0,0 +1,3 @@
+module top;
+  soc u_soc ();
+endmodule
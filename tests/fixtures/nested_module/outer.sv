@@ -0,0 +1,5 @@
+module outer;
+  module inner;
+  endmodule
+  inner u_inner ();
+endmodule
@@ -0,0 +1,4 @@
+module top;
+  outer u_outer ();
+  inner u_inner ();
+endmodule
@@ -0,0 +1,3 @@
+module dff_cell (output reg q, input wire d, input wire clk);
+  always @(posedge clk) q <= d;
+endmodule
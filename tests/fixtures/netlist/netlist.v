@@ -0,0 +1,11 @@
+module netlist (output wire y, output wire q, input wire a, input wire b, input wire clk);
+  wire n1, n2, n3;
+  and g1 (n1, a, b);
+  or g2 (n2, a, b);
+  nand g3 (n3, n1, n2);
+  buf g4 (y, n3);
+  not g5 (n4, y);
+  pullup (n4);
+  bufif1 g6 (n5, n4, clk);
+  dff_cell u_ff (.q(q), .d(n5), .clk(clk));
+endmodule
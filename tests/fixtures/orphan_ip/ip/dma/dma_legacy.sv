@@ -0,0 +1 @@
+// Kept for the old flow, everything moved to dma.sv
@@ -0,0 +1,3 @@
+package dma_pkg;
+  typedef logic [31:0] desc_t;
+endpackage
@@ -0,0 +1,6 @@
+top.sv
+core.sv
+// The DMA isn't instantiated yet
+ip/dma/dma_pkg.sv
+ip/dma/dma.sv
+ip/dma/dma_legacy.sv
@@ -0,0 +1,2 @@
+entity glue is
+end entity;
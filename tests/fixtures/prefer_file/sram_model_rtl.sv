@@ -0,0 +1,3 @@
+module sram_model(input logic clk);
+    // Behavioral model for simulation
+endmodule
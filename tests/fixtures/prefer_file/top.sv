@@ -0,0 +1,3 @@
+module top(input logic clk);
+    sram_model u_sram(.clk(clk));
+endmodule
@@ -0,0 +1,4 @@
+module harness;
+  logic clk;
+  tb_main u_tb (.clk(clk));
+endmodule
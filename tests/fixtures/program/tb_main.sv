@@ -0,0 +1,3 @@
+program automatic tb_main (input logic clk);
+  initial $display("start");
+endprogram
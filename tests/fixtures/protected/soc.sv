@@ -0,0 +1,4 @@
+module soc;
+  logic clk, done;
+  vendor_ip u_ip (.clk(clk), .done(done));
+endmodule
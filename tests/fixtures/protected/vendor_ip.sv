@@ -0,0 +1,10 @@
+module vendor_ip (input logic clk, output logic done);
+`pragma protect begin_protected
+`pragma protect encrypt_agent = "Vendor Encryptor"
+`pragma protect key_block
+aGVsbG8gd29ybGQgdGhpcyBpcyBub3QgcmVhbGx5IGVuY3J5cHRlZA==
+`pragma protect data_block
+U29tZSBmYWtlIGNpcGhlcnRleHQgdGhhdCBpc24ndCBTeXN0ZW1WZXJpbG9n
+IHsgfSBbIF0gbW9kdWxlIGVuZG1vZHVsZQ==
+`pragma protect end_protected
+endmodule
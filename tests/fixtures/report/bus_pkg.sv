@@ -0,0 +1,3 @@
+package bus_pkg;
+  typedef logic [7:0] byte_t;
+endpackage
@@ -0,0 +1,8 @@
+`include "defs.svh"
+
+module top;
+  import bus_pkg::*;
+  logic [`BUS_WIDTH-1:0] data;
+  leaf u_leaf (.data(data));
+  vendor_ip u_ip ();
+endmodule
@@ -0,0 +1,2 @@
+module a_top;
+endmodule
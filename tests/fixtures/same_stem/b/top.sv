@@ -0,0 +1,3 @@
+module b_top;
+  a_top u();
+endmodule
@@ -0,0 +1,3 @@
+module top;
+    uart u();
+endmodule
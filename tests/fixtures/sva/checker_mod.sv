@@ -0,0 +1,3 @@
+module checker_mod(input logic clk, input logic x);
+    assert property (sva_pkg::p_x(clk, x));
+endmodule
@@ -0,0 +1,5 @@
+package sva_pkg;
+    property p_x(clk, x);
+        @(posedge clk) !$isunknown(x);
+    endproperty
+endpackage
@@ -0,0 +1 @@
+module broken(;
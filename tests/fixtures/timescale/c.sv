@@ -0,0 +1,3 @@
+`timescale 1ns/1ps
+module c;
+endmodule
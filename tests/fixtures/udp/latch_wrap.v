@@ -0,0 +1,3 @@
+module latch_wrap (output wire q, input wire d, input wire en);
+  my_latch u_latch (q, d, en);
+endmodule
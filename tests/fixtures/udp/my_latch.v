@@ -0,0 +1,11 @@
+primitive my_latch (q, d, en);
+  output q;
+  reg q;
+  input d, en;
+  table
+    // d en : q : q+
+       0  1 : ? : 0;
+       1  1 : ? : 1;
+       ?  0 : ? : -;
+  endtable
+endprimitive
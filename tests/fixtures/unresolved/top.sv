@@ -0,0 +1,3 @@
+module top;
+  missing_cell u();
+endmodule
@@ -0,0 +1,3 @@
+module sub;
+  vendor_ip u_ip ();
+endmodule
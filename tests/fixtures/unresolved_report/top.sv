@@ -0,0 +1,7 @@
+module top;
+  import missing_pkg::*;
+  vendor_ip u_ip0 ();
+  vendor_ip u_ip1 ();
+  sky130_fd_sc_hd__inv_1 u_inv (.A(1'b0), .Y());
+  sub u_sub ();
+endmodule
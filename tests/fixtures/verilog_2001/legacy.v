@@ -0,0 +1,7 @@
+module legacy (input wire clk, output wire logic);
+  wire bit;
+  wire do;
+  assign bit = clk;
+  assign do = bit;
+  assign logic = do;
+endmodule
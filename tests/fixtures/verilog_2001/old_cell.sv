@@ -0,0 +1,5 @@
+`begin_keywords "1364-2001"
+module old_cell (input wire bit, output wire do);
+  assign do = bit;
+endmodule
+`end_keywords
@@ -0,0 +1,5 @@
+module wrapper (input logic clk, output logic out);
+  logic mid;
+  legacy u_legacy (clk, mid);
+  old_cell u_cell (mid, out);
+endmodule
@@ -0,0 +1,3 @@
+class bus_driver;
+  virtual my_bus_if #(32) vif;
+endclass
@@ -0,0 +1,3 @@
+interface my_bus_if #(parameter int WIDTH = 8);
+  logic [WIDTH-1:0] data;
+endinterface
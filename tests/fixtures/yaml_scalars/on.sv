@@ -0,0 +1,6 @@
+module on;
+endmodule
+
+module off;
+  y u_y ();
+endmodule
@@ -0,0 +1,4 @@
+module yes;
+  on u_on ();
+  off u_off ();
+endmodule
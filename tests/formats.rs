@@ -0,0 +1,552 @@
+mod common;
+
+use common::{fixture, order, run, stderr};
+use std::process::Command;
+
+const REPORT: &[&str] = &["top.sv", "leaf.sv", "bus_pkg.sv"];
+
+/// The sources of the spaced fixture with an include path and defines, the leaf is in a directory with a space
+const SPACED: &[&str] = &["+incdir+IP Cores/include", "-D", "WIDTH=8", "-D", "SIM", "top.sv", "IP Cores/uart.sv"];
+
+/// Whether a tool the test runs is installed, the tests using it pass without it
+fn installed(tool: &str) -> bool {
+    let found = Command::new(tool).arg("--version").output().is_ok_and(|output| output.status.success());
+    if !found {
+        eprintln!("{} is not installed, skipping", tool);
+    }
+    found
+}
+
+#[test]
+fn make_fragment_accepted_by_make() {
+    if !installed("make") {
+        return
+    }
+    let dir = common::temp_dir("make");
+    let fragment = dir.join("deps.mk");
+    let output = run(&fixture("report"), &[&["--format", "make", "-o", fragment.to_str().unwrap()], REPORT].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    let makefile = dir.join("Makefile");
+    std::fs::write(&makefile, format!("\
+include {}
+all: $(patsubst %.sv,build/%.o,$(SV_SOURCES_ORDERED))
+build/%.o:
+\t@echo compile $<
+", fragment.display())).unwrap();
+
+    let make = Command::new("make").arg("-n").arg("-f").arg(&makefile).arg("all").current_dir(fixture("report")).output().unwrap();
+    assert!(make.status.success(), "{}", String::from_utf8_lossy(&make.stderr));
+    assert_eq!(String::from_utf8_lossy(&make.stdout), "echo compile bus_pkg.sv\necho compile leaf.sv\necho compile top.sv\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn same_object_for_two_files_rejected() {
+    let output = run(&fixture("same_stem"), &["--format", "make", "a/top.sv", "b/top.sv"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(stderr(&output).contains("a/top.sv and b/top.sv would both build build/top.o"), "{}", stderr(&output));
+
+    let fragment = order("same_stem", &["--format", "make", "--object-template", "build/{dir}/{stem}.o", "a/top.sv", "b/top.sv"]);
+    assert!(fragment.contains("\nbuild/a/top.o: a/top.sv\n"), "{}", fragment);
+    assert!(fragment.contains("\nbuild/b/top.o: b/top.sv a/top.sv\n"), "{}", fragment);
+}
+
+#[test]
+fn same_ninja_output_for_two_files_rejected() {
+    let output = run(&fixture("same_stem"), &["--format", "ninja", "--compile-cmd", "vlog $in", "a/top.sv", "b/top.sv"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("a/top.sv and b/top.sv would both build build/top.o"), "{}", stderr(&output));
+
+    let build = order("same_stem", &["--format", "ninja", "--compile-cmd", "vlog $in", "--object-template", "build/{dir}/{stem}.o", "a/top.sv", "b/top.sv"]);
+    assert!(build.contains("\nbuild build/a/top.o: sv_compile a/top.sv\n"), "{}", build);
+    assert!(build.contains("\nbuild build/b/top.o: sv_compile b/top.sv | build/a/top.o\n"), "{}", build);
+}
+
+#[test]
+fn ninja_build_escapes_paths() {
+    let leaf = "IP Cores:v1/$uart.sv";
+    let dir = escaped_design("ninja", leaf);
+    let output = run(&dir, &["--format", "ninja", "--compile-cmd", "vlog $in && touch $out", "-o", "build.ninja", "top.sv", leaf]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(std::fs::read_to_string(dir.join("build.ninja")).unwrap(), "\
+rule sv_compile
+  command = vlog $in && touch $out
+  description = Compiling $in
+
+build build/$$uart.o: sv_compile IP$ Cores$:v1/$$uart.sv
+
+build build/top.o: sv_compile top.sv | build/$$uart.o
+
+build all: phony build/$$uart.o build/top.o
+default all
+");
+    if installed("ninja") {
+        let ninja = Command::new("ninja").args(["-n", "-f", "build.ninja"]).current_dir(&dir).output().unwrap();
+        assert!(ninja.status.success(), "{}", String::from_utf8_lossy(&ninja.stdout));
+        assert!(String::from_utf8_lossy(&ninja.stdout).contains("Compiling top.sv"), "{}", String::from_utf8_lossy(&ninja.stdout));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn questa_only_compiles_systemverilog_with_sv() {
+    assert_eq!(order("library_file", &["--format", "questa", "top2.sv", "cells.v"]), "\
+#!/bin/sh
+# Compiles the sources in dependency order
+set -e
+vlog \\
+    cells.v
+vlog -sv \\
+    top2.sv
+");
+    let dir = common::temp_dir("questa");
+    let args_file = dir.join("args.f");
+    let script = order("library_file", &["--format", "questa", "--args-file", args_file.to_str().unwrap(), "top2.sv", "cells.v"]);
+    assert!(script.ends_with(&format!("\nvlog -f {}\n", args_file.display())), "{}", script);
+    assert_eq!(std::fs::read_to_string(&args_file).unwrap(), "cells.v\ntop2.sv\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn questa_compiles_each_library_with_work() {
+    let script = order("libmap", &["--format", "questa", "--libmap", "lib.map", "-D", "SIM", "rtl/top.sv", "gate/gate_cell.v"]);
+    assert_eq!(script, "\
+#!/bin/sh
+# Compiles the sources in dependency order
+set -e
+vlib gates
+vlib rtl
+vlog -work gates \\
+    +define+SIM \\
+    gate/gate_cell.v
+vlog -sv -work rtl \\
+    +define+SIM \\
+    rtl/top.sv
+");
+    if installed("shellcheck") {
+        let mut shellcheck = Command::new("shellcheck").arg("-")
+            .stdin(std::process::Stdio::piped()).stdout(std::process::Stdio::piped())
+            .spawn().unwrap();
+        std::io::Write::write_all(&mut shellcheck.stdin.take().unwrap(), script.as_bytes()).unwrap();
+        let shellcheck = shellcheck.wait_with_output().unwrap();
+        assert!(shellcheck.status.success(), "{}", String::from_utf8_lossy(&shellcheck.stdout));
+    }
+}
+
+#[test]
+fn fusesoc_core_reads_back() {
+    let core = order("report", &[
+        &["-q", "--format", "fusesoc", "--core-name", "acme:ip:bus:1.0", "-D", "Y=0x10", "-D", "ON", "-D", "SCALE=1e3", "defs.svh"],
+        REPORT,
+    ].concat());
+    assert!(core.starts_with("CAPI=2:\n"), "{}", core);
+    assert_eq!(common::yaml::parse(&core), serde_json::json!({
+        "CAPI=2": null,
+        "name": "acme:ip:bus:1.0",
+        "filesets": {"rtl": {
+            "files": ["bus_pkg.sv", "leaf.sv", "top.sv", {"defs.svh": {"is_include_file": true}}],
+            "file_type": "systemVerilogSource",
+        }},
+        "parameters": {
+            "ON": {"datatype": "bool", "default": true, "paramtype": "vlogdefine"},
+            "SCALE": {"datatype": "str", "default": "1e3", "paramtype": "vlogdefine"},
+            "Y": {"datatype": "str", "default": "0x10", "paramtype": "vlogdefine"},
+        },
+        "targets": {"default": {"filesets": ["rtl"], "parameters": ["ON", "SCALE", "Y"]}},
+    }));
+}
+
+#[test]
+fn yaml_names_stay_strings() {
+    let args = ["yes.sv", "on.sv", "y.sv"];
+    let yaml = common::yaml::parse(&order("yaml_scalars", &[&["--format", "yaml"], &args[..]].concat()));
+    let json: serde_json::Value = serde_json::from_str(&order("yaml_scalars", &[&["--format", "json"], &args[..]].concat())).unwrap();
+    assert_eq!(yaml, json);
+    assert_eq!(json["files"][0]["defines"]["modules"], serde_json::json!(["off", "on"]));
+}
+
+#[test]
+fn cmake_keeps_backslashes_in_defines() {
+    assert_eq!(order("report", &[&["--format", "cmake", "-i", "inc\\dir", "-D", "MSG=\"a\\nb\"", "-D", "P=C:\\x;$y"], REPORT].concat()), r#"set(SV_SOURCES_ORDERED
+    "bus_pkg.sv"
+    "leaf.sv"
+    "top.sv"
+)
+set(SV_INCLUDE_DIRS
+    "inc/dir"
+)
+set(SV_DEFINES
+    "MSG=\"a\\nb\""
+    "P=C:\\x\;\$y"
+)
+"#);
+}
+
+/// Reads RFC 4180 records, the fields of the quoted ones may have commas, quotes and line breaks
+#[test]
+fn mermaid_snapshot() {
+    assert_eq!(order("report", &[&["--format", "mermaid"], REPORT].concat()), "\
+graph TD
+    n0[\"bus_pkg\"]
+    n1[\"leaf\"]
+    n2[\"top\"]
+    n1 --> n0
+    n2 --> n0
+    n2 --> n1
+    classDef root stroke-width:3px
+    class n2 root
+");
+}
+
+#[test]
+fn mermaid_summarizes_past_max_nodes() {
+    // The IDs stay those of the full diagram, what is left out is counted under each file
+    assert_eq!(order("diamond", &[&["--format", "mermaid", "--max-nodes", "2"], DIAMOND].concat()), "\
+graph TD
+    n1[\"left\"]
+    n3[\"top\"]
+    n1_more[\"1 more file\"]
+    n1 --> n1_more
+    n3 --> n1
+    n3_more[\"2 more files\"]
+    n3 --> n3_more
+    classDef root stroke-width:3px
+    class n3 root
+");
+}
+
+fn csv_records(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            '\r' if !quoted && chars.peek() == Some(&'\n') => (),
+            c => field.push(c),
+        }
+    }
+    assert!(!quoted && field.is_empty(), "the last record doesn't end with a line break");
+    records
+}
+
+#[test]
+fn hdlmake_manifest_snapshot() {
+    assert_eq!(order("spaced", &[&["--format", "hdlmake"], SPACED].concat()), "\
+files = [
+    \"IP Cores/uart.sv\",
+    \"top.sv\",
+]
+include_dirs = [
+    \"IP Cores/include\",
+]
+defines = [
+    \"SIM\",
+    \"WIDTH=8\",
+]
+");
+}
+
+#[test]
+fn hdlmake_manifest_per_dir_snapshot() {
+    // The include paths are relative to each manifest, also the one that doesn't exist yet
+    assert_eq!(order("spaced", &[&["--format", "hdlmake", "--manifest-per-dir", "+incdir+IP Cores/gen"], SPACED].concat()), "\
+# ./Manifest.py
+files = [
+    \"top.sv\",
+]
+include_dirs = [
+    \"IP Cores/gen\",
+    \"IP Cores/include\",
+]
+defines = [
+    \"SIM\",
+    \"WIDTH=8\",
+]
+modules = {\"local\": [
+    \"IP Cores\",
+]}
+
+# IP Cores/Manifest.py
+files = [
+    \"uart.sv\",
+]
+include_dirs = [
+    \"gen\",
+    \"include\",
+]
+defines = [
+    \"SIM\",
+    \"WIDTH=8\",
+]
+");
+}
+
+#[test]
+fn csv_edges_rebuild_the_graph() {
+    let sources = ["top.sv", "fifo.sv", "with,comma/cfg_pkg.sv"];
+    let dir = common::temp_dir("csv");
+    let prefix = format!("{}/", dir.display());
+    let output = run(&fixture("csv"), &[&["--format", "csv", "-o", &prefix], &sources[..]].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let nodes = csv_records(&std::fs::read_to_string(dir.join("nodes.csv")).unwrap());
+    assert_eq!(nodes, [
+        ["path", "modules_defined", "packages_defined"],
+        ["fifo.sv", "1", "0"],
+        ["top.sv", "1", "0"],
+        ["with,comma/cfg_pkg.sv", "0", "1"],
+    ]);
+    let edges = csv_records(&std::fs::read_to_string(dir.join("edges.csv")).unwrap());
+    assert_eq!(edges[0], ["from", "to", "reason_symbol", "reason_kind"]);
+    let mut from_csv: Vec<_> = edges[1..].iter().map(|edge| (edge[0].clone(), edge[1].clone())).collect();
+    from_csv.sort_unstable();
+
+    let report: serde_json::Value = serde_json::from_str(&order("csv", &[&["--format", "json"], &sources[..]].concat())).unwrap();
+    let mut from_report = Vec::new();
+    for file in report["files"].as_array().unwrap() {
+        for dep in file["dependencies"].as_array().unwrap() {
+            from_report.push((file["path"].as_str().unwrap().to_owned(), dep.as_str().unwrap().to_owned()));
+        }
+    }
+    from_report.sort_unstable();
+    assert_eq!(from_csv, from_report);
+    assert_eq!(edges[1], ["fifo.sv", "with,comma/cfg_pkg.sv", "cfg_pkg", "package/class"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+const DIAMOND: &[&str] = &["top.sv", "right.sv", "left.sv", "base_pkg.sv"];
+
+#[test]
+fn diamond_in_three_groups() {
+    assert_eq!(order("diamond", &[&["--format", "groups"], DIAMOND].concat()), "\
+# group 0
+base_pkg.sv
+
+# group 1
+right.sv
+left.sv
+
+# group 2
+top.sv
+");
+    let groups: Vec<Vec<String>> = serde_json::from_str(&order("diamond", &[&["--format", "groups-json"], DIAMOND].concat())).unwrap();
+    assert_eq!(groups, [vec!["base_pkg.sv"], vec!["right.sv", "left.sv"], vec!["top.sv"]]);
+}
+
+/// A top instantiating a leaf at a path that most formats have to quote or escape, in a directory of the test
+fn escaped_design(name: &str, leaf: &str) -> std::path::PathBuf {
+    let dir = common::temp_dir(name);
+    std::fs::create_dir_all(dir.join(leaf).parent().unwrap()).unwrap();
+    std::fs::write(dir.join(leaf), "module uart;\nendmodule\n").unwrap();
+    std::fs::write(dir.join("top.sv"), "module top;\n    uart u();\nendmodule\n").unwrap();
+    dir
+}
+
+#[test]
+fn null_list_read_back_by_xargs() {
+    let leaf = "IP Cores/\"$uart\".sv";
+    let dir = escaped_design("xargs", leaf);
+    let output = run(&dir, &["-0", "top.sv", leaf]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let mut xargs = Command::new("xargs").args(["-0", "printf", "%s\\n"])
+        .stdin(std::process::Stdio::piped()).stdout(std::process::Stdio::piped())
+        .spawn().unwrap();
+    std::io::Write::write_all(&mut xargs.stdin.take().unwrap(), &output.stdout).unwrap();
+    let xargs = xargs.wait_with_output().unwrap();
+    assert!(xargs.status.success());
+    assert_eq!(String::from_utf8_lossy(&xargs.stdout), format!("{}\ntop.sv\n", leaf));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn vcs_f_read_back_as_a_filelist() {
+    let leaf = "IP Cores/\"$uart\".sv";
+    let dir = escaped_design("vcs-f", leaf);
+    let output = run(&dir, &["--format", "vcs-f", "-o", "order.f", "top.sv", leaf]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = run(&dir, &["-f", "order.f"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), format!("{}\ntop.sv\n", leaf));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn vcs_f_snapshot() {
+    assert_eq!(order("spaced", &[&["--format", "vcs-f", "--sverilog"], SPACED].concat()), "\
+-sverilog
+\"+incdir+IP Cores/include\"
++define+SIM
++define+WIDTH=8
+\"IP Cores/uart.sv\"
+top.sv
+");
+}
+
+#[test]
+fn xrun_f_snapshot() {
+    let args = order("spaced", &[&["--format", "xrun-f"], SPACED].concat());
+    let (invocation, args) = args.split_once('\n').unwrap();
+    // The comment records the invocation, after the path of the binary
+    assert!(invocation.starts_with("// Generated by "), "{}", invocation);
+    assert!(invocation.ends_with(" --format xrun-f '+incdir+IP Cores/include' -D WIDTH=8 -D SIM top.sv 'IP Cores/uart.sv'"), "{}", invocation);
+    assert_eq!(args, "\
+-incdir \"IP Cores/include\"
+-define SIM
+-define WIDTH=8
+-sv
+\"IP Cores/uart.sv\"
+top.sv
+");
+}
+
+#[test]
+fn verilator_snapshot() {
+    assert_eq!(order("spaced", &[&["--format", "verilator", "--top", "top"], SPACED].concat()), "\
+\"+incdir+IP Cores/include\"
++define+SIM
++define+WIDTH=8
+--top-module top
+\"IP Cores/uart.sv\"
+top.sv
+");
+    if installed("verilator") {
+        let dir = common::temp_dir("verilator");
+        let args_file = dir.join("order.f");
+        let output = run(&fixture("spaced"), &[&["--format", "verilator", "--top", "top", "-o", args_file.to_str().unwrap()], SPACED].concat());
+        assert!(output.status.success(), "{}", stderr(&output));
+        let verilator = Command::new("verilator").arg("--lint-only").arg("-f").arg(&args_file).current_dir(fixture("spaced")).output().unwrap();
+        assert!(verilator.status.success(), "{}", String::from_utf8_lossy(&verilator.stderr));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn vivado_tcl_read_back_by_tclsh() {
+    let leaf = "IP Cores/[x] {$uart.sv";
+    let dir = escaped_design("vivado-tcl", leaf);
+    let output = run(&dir, &["--format", "vivado-tcl", "-o", "order.tcl", "+incdir+IP Cores", "-D", "A=$x [y]", "top.sv", leaf]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    // Each command prints the words it got, in place of the Vivado one
+    std::fs::write(dir.join("read_back.tcl"), "\
+proc read_verilog {args} { puts [lindex $args end] }
+proc set_property {name values fileset} { foreach value $values { puts \"$name $value\" } }
+proc current_fileset {} {}
+source order.tcl
+").unwrap();
+    let tclsh = match Command::new("tclsh").arg("read_back.tcl").current_dir(&dir).output() {
+        Ok(tclsh) => tclsh,
+        Err(_) => return eprintln!("tclsh is not installed, skipping"),
+    };
+    assert!(tclsh.status.success(), "{}", String::from_utf8_lossy(&tclsh.stderr));
+    assert_eq!(String::from_utf8_lossy(&tclsh.stdout), format!("{}\ntop.sv\ninclude_dirs IP Cores\nverilog_define A=$x [y]\n", leaf));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn vivado_tcl_snapshot() {
+    assert_eq!(order("spaced", &[&["--format", "vivado-tcl"], SPACED].concat()), "\
+read_verilog -sv {IP Cores/uart.sv}
+read_verilog -sv top.sv
+set_property include_dirs {{IP Cores/include}} [current_fileset]
+set_property verilog_define {SIM WIDTH=8} [current_fileset]
+");
+    assert_eq!(order("spaced", &[&["--format", "vivado-tcl", "--vivado-mode", "project"], SPACED].concat()), "\
+add_files -norecurse {IP Cores/uart.sv}
+add_files -norecurse top.sv
+set_property include_dirs {{IP Cores/include}} [current_fileset]
+set_property verilog_define {SIM WIDTH=8} [current_fileset]
+");
+}
+
+#[test]
+fn make_rules_read_back_by_make() {
+    if !installed("make") {
+        return
+    }
+    let leaf = "IP Cores:v1/$uart.sv";
+    let dir = escaped_design("make-escapes", leaf);
+    let output = run(&dir, &["--format", "make", "-o", "deps.mk", "top.sv", leaf]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    std::fs::write(dir.join("Makefile"), "\
+include deps.mk
+all: build/top.o build/$$uart.o
+build/%.o:
+\t@printf '%s <- %s\\n' '$@' '$<'
+").unwrap();
+    let make = Command::new("make").arg("all").current_dir(&dir).output().unwrap();
+    assert!(make.status.success(), "{}", String::from_utf8_lossy(&make.stderr));
+    assert_eq!(String::from_utf8_lossy(&make.stdout), format!("build/top.o <- top.sv\nbuild/$uart.o <- {}\n", leaf));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn bazel_targets_of_two_packages() {
+    assert_eq!(order("bazel", &["--format", "bazel", "rtl/top.sv", "ip/leaf.sv", "ip/bus_pkg.sv"]), r#"# //:BUILD.bazel
+
+sv_library(
+    name = "all",
+    deps = [
+        "//rtl:top",
+    ],
+)
+
+# //ip:BUILD.bazel
+
+sv_library(
+    name = "bus_pkg",
+    srcs = ["bus_pkg.sv"],
+)
+
+sv_library(
+    name = "leaf",
+    srcs = ["leaf.sv"],
+    deps = [
+        ":bus_pkg",
+    ],
+)
+
+# //rtl:BUILD.bazel
+
+sv_library(
+    name = "top",
+    srcs = ["top.sv"],
+    deps = [
+        "//ip:leaf",
+    ],
+)
+"#);
+}
+
+#[test]
+fn bazel_rejects_cycles_no_root_reaches() {
+    let output = run(&fixture("cycle"), &["--format", "bazel", "ping.sv", "pong.sv"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(stderr(&output).contains("Error: ping.sv, pong.sv depend on each other, Bazel doesn't allow cycles\n"), "{}", stderr(&output));
+    // The other outputs still have the files of the cycle
+    assert_eq!(order("cycle", &["ping.sv", "pong.sv"]), "pong.sv\nping.sv\n");
+    let output = run(&fixture("cycle"), &["--format", "groups", "ping.sv", "pong.sv"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "# group 0\npong.sv\nping.sv\n");
+    assert!(stderr(&output).contains("Warning: ping.sv, pong.sv depend on each other, they are all in the same group\n"), "{}", stderr(&output));
+}
+
+#[test]
+fn bazel_rejects_package_names_with_spaces() {
+    let output = run(&fixture("spaced"), &["--format", "bazel", "top.sv", "IP Cores/uart.sv"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("IP Cores/uart.sv is in IP Cores, which isn't a valid Bazel package name"), "{}", stderr(&output));
+}
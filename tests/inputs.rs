@@ -0,0 +1,147 @@
+mod common;
+
+use common::{fixture, order, run, sources, stderr};
+
+#[test]
+fn unused_library_file_left_out() {
+    assert_eq!(order("library_file", &["-f", "unused.f"]), "top.sv\n");
+    assert_eq!(order("library_file", &["top.sv", "--library-file", "cells.v"]), "top.sv\n");
+}
+
+#[test]
+fn used_library_file_ordered() {
+    assert_eq!(order("library_file", &["-f", "used.f"]), "cells.v\ntop2.sv\n");
+    assert_eq!(order("library_file", &["top2.sv", "--library-file", "cells.v"]), "cells.v\ntop2.sv\n");
+}
+
+#[test]
+fn header_declaring_a_package_ordered() {
+    let output = run(&fixture("header_package"), &["top.sv", "pkg.svh", "defs.svh"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "pkg.svh\ntop.sv\n");
+    assert!(stderr(&output).contains("Warning: defs.svh looks like a header, leaving it out of the order"), "{}", stderr(&output));
+}
+
+#[test]
+fn sv_file_with_only_defines_ordered() {
+    // Only the header extensions make a file that declares nothing a header
+    let output = run(&fixture("macro_deps"), &["--macro-deps", "user.sv", "defines_pkg.sv"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "defines_pkg.sv\nuser.sv\n");
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn protected_region_skipped() {
+    // The module header is outside the fake encrypted body
+    let output = run(&fixture("protected"), &["soc.sv", "vendor_ip.sv"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "vendor_ip.sv\nsoc.sv\n");
+    assert_eq!(stderr(&output), "Warning: vendor_ip.sv: skipped 1 encrypted region(s), only the visible parts are ordered\n");
+}
+
+#[test]
+fn verilog_2001_keywords_as_names() {
+    // legacy.v is Verilog-2001 by its extension, old_cell.sv by its `begin_keywords
+    let output = run(&fixture("verilog_2001"), &["wrapper.sv", "old_cell.sv", "legacy.v"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "old_cell.sv\nlegacy.v\nwrapper.sv\n");
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn exotic_instances_collected_without_skipping() {
+    // Instance arrays, generated instances, unnamed gates and a bind to an instance list
+    let output = run(&fixture("exotic_instances"), &["-v", "--strict-parse", "exotic.sv", "leaf_cell.sv"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "leaf_cell.sv\nexotic.sv\n");
+    assert!(!stderr(&output).contains("skipped"), "{}", stderr(&output));
+}
+
+#[test]
+fn nested_filelists_and_their_cycle() {
+    assert_eq!(order("nested_filelist", &["-f", "top.f"]), "ip/axi/axi_pkg.sv\nip/axi/axi_xbar.sv\nip/soc.sv\ntop.sv\n");
+    // cycle.f comes back through two levels of -F
+    let output = run(&fixture("nested_filelist"), &["-f", "cycle.f"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let error = stderr(&output);
+    assert!(error.starts_with("Error: Filelists include each other: "), "{}", error);
+    let chain: Vec<_> = error.trim_end().rsplit(": ").next().unwrap().split(" -> ").map(|f| f.rsplit("nested_filelist/").next().unwrap()).collect();
+    assert_eq!(chain, ["cycle.f", "ip/soc_loop.f", "ip/axi/axi_loop.f", "cycle.f"]);
+}
+
+#[test]
+fn glob_sources_recurse_with_double_star() {
+    assert_eq!(sources("glob", &["rtl/**/*.sv"]), ["rtl/core/debug.sv", "rtl/core/pkg/types_pkg.sv", "rtl/core/core.sv", "rtl/top.sv"]);
+    assert_eq!(sources("glob", &["--glob", "rtl/**/[ct]*.sv"]), ["rtl/core/pkg/types_pkg.sv", "rtl/core/core.sv", "rtl/top.sv"]);
+    // A listed file also matched by the pattern is only ordered once
+    assert_eq!(sources("glob", &["rtl/top.sv", "rtl/**/*.sv"]).len(), 4);
+    let output = run(&fixture("glob"), &["rtl/**/*.vhd", "rtl/top.sv"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), "Error: No files match rtl/**/*.vhd\n");
+    assert_eq!(sources("glob", &["--allow-empty-glob", "rtl/**/*.vhd", "rtl/**/*.sv"]).len(), 4);
+}
+
+#[test]
+fn excluded_only_provider_leaves_its_module_unresolved() {
+    let args = ["--exclude", "*/core.sv", "rtl/**/*.sv"];
+    let output = run(&fixture("glob"), &[&["-v"], &args[..]].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "rtl/core/debug.sv\nrtl/core/pkg/types_pkg.sv\nrtl/top.sv\n");
+    // It isn't even parsed
+    assert!(stderr(&output).starts_with("Excluded 1 sources\n"), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("core.sv"), "{}", stderr(&output));
+    let output = run(&fixture("glob"), &[&["--strict"], &args[..]].concat());
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Error: rtl/top.sv: unresolved core\n"), "{}", stderr(&output));
+}
+
+#[test]
+fn every_broken_file_reported_with_keep_going() {
+    let args = ["--keep-going", "--strict-parse", "top.sv", "bad_b.sv", "leaf.sv", "bad_a.sv"];
+    let output = run(&fixture("keep_going"), &args);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    // Sorted by path, whatever the order of the arguments
+    let error = stderr(&output);
+    let a = error.find("Error: While parsing bad_a.sv: Syntax error at bad_a.sv:2:9\n").unwrap_or_else(|| panic!("{}", error));
+    let b = error.find("Error: While parsing bad_b.sv: Syntax error at bad_b.sv:1:15\n").unwrap_or_else(|| panic!("{}", error));
+    assert!(a < b, "{}", error);
+    assert!(error.ends_with("Error: 2 of 4 files failed to parse\n"), "{}", error);
+    let output = run(&fixture("keep_going"), &[&["--partial-output"], &args[..]].concat());
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "leaf.sv\ntop.sv\n");
+    assert!(stderr(&output).contains("Warning: the output only has the files that parsed, it may be wrong\n"), "{}", stderr(&output));
+}
+
+#[test]
+fn command_line_overrides_the_config() {
+    // The config lists the sources, WIDTH=8 and a space separator
+    assert_eq!(order("config_file", &[]), "leaf.sv top.sv");
+    assert_eq!(order("config_file", &["--separator", ","]), "leaf.sv,top.sv");
+    assert_eq!(order("config_file", &["--format", "vcs-f", "-D", "WIDTH=16"]), "+define+SIM\n+define+WIDTH=16\nleaf.sv\ntop.sv\n");
+    // Sources on the command line replace those of the config
+    assert_eq!(order("config_file", &["top.sv"]), "top.sv");
+    let output = run(&fixture("config_file"), &["--no-config"]);
+    assert!(!output.status.success());
+    assert_eq!(stderr(&output), "Error: No source files given\n");
+}
+
+#[test]
+#[cfg(unix)]
+fn one_node_per_file_across_spellings_and_symlinks() {
+    let dir = common::temp_dir("symlink");
+    std::fs::create_dir_all(dir.join("rtl")).unwrap();
+    std::fs::write(dir.join("rtl/leaf.sv"), "module leaf;\nendmodule\n").unwrap();
+    std::fs::write(dir.join("top.sv"), "module top;\n    leaf u_leaf ();\nendmodule\n").unwrap();
+    std::os::unix::fs::symlink("rtl", dir.join("alias")).unwrap();
+    let absolute = dir.join("top.sv");
+    let output = run(&dir, &["-v", "top.sv", "rtl/leaf.sv", absolute.to_str().unwrap(), "alias/leaf.sv"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    // The first spelling is kept
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "rtl/leaf.sv\ntop.sv\n");
+    assert!(stderr(&output).contains(&format!("Ignoring {}, listed more than once\n", absolute.display())), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Ignoring alias/leaf.sv, listed more than once\n"), "{}", stderr(&output));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
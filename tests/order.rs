@@ -0,0 +1,253 @@
+mod common;
+
+use common::{fixture, order, run, sources, stderr};
+
+#[test]
+fn interface_before_its_instance() {
+    assert_eq!(sources("interface", &["top.sv", "if_defs.sv"]), ["if_defs.sv", "top.sv"]);
+}
+
+#[test]
+fn export_chain_in_order() {
+    // The umbrella package only exports axi_pkg, it uses nothing from it
+    assert_eq!(sources("export_chain", &["user.sv", "umbrella_pkg.sv", "axi_pkg.sv"]), ["axi_pkg.sv", "umbrella_pkg.sv", "user.sv"]);
+}
+
+#[test]
+fn package_in_header_types() {
+    // alu only names op_pkg in its parameter and port types
+    assert_eq!(sources("header_types", &["alu.sv", "op_pkg.sv"]), ["op_pkg.sv", "alu.sv"]);
+}
+
+#[test]
+fn propagate_defines_from_all_branches() {
+    // BAR is only defined by the parse of a.sv with FOO flipped
+    let order = order("branch_defines", &["--propagate-defines", "--all-ifdef-branches", "b.sv", "a.sv"]);
+    assert_eq!(order, "a.sv\nb.sv\n");
+}
+
+#[test]
+fn macro_provider_before_user() {
+    assert_eq!(sources("macro_deps", &["--macro-deps", "user.sv", "defines_pkg.sv"]), ["defines_pkg.sv", "user.sv"]);
+    // Propagating the define lets user.sv parse, the edge is only for --macro-deps
+    assert_eq!(sources("macro_deps", &["--propagate-defines", "user.sv", "defines_pkg.sv"]), ["user.sv", "defines_pkg.sv"]);
+}
+
+#[test]
+fn failed_branch_is_skipped() {
+    // The SIM side uses a macro nothing defines, the other side still orders the file
+    let output = run(&fixture("bad_branch"), &["--all-ifdef-branches", "top.sv", "leaf.sv"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "leaf.sv\ntop.sv\n");
+    assert!(stderr(&output).contains("skipping the parse with `SIM flipped"), "{}", stderr(&output));
+}
+
+#[test]
+fn base_class_before_derived() {
+    assert_eq!(sources("extends", &["my_driver.sv", "base_driver.sv"]), ["base_driver.sv", "my_driver.sv"]);
+}
+
+#[test]
+fn package_property_in_assertion() {
+    // The assertion is the only place checker_mod names sva_pkg
+    assert_eq!(sources("sva", &["checker_mod.sv", "sva_pkg.sv"]), ["sva_pkg.sv", "checker_mod.sv"]);
+}
+
+#[test]
+fn package_of_an_unscoped_symbol() {
+    // Nothing but NUM_CORES links soc.sv to cfg_pkg
+    assert_eq!(sources("deep_resolve", &["--deep-resolve", "soc.sv", "cfg_pkg.sv"]), ["cfg_pkg.sv", "soc.sv"]);
+    assert_eq!(sources("deep_resolve", &["soc.sv", "cfg_pkg.sv"]), ["soc.sv", "cfg_pkg.sv"]);
+}
+
+#[test]
+fn symbol_of_the_imported_package() {
+    // core.sv imports other_pkg, the NUM_CORES of cfg_pkg isn't the one it uses
+    assert_eq!(sources("deep_resolve", &["--deep-resolve", "core.sv", "cfg_pkg.sv", "other_pkg.sv"]), ["other_pkg.sv", "core.sv", "cfg_pkg.sv"]);
+}
+
+#[test]
+fn symbol_of_two_packages_is_a_duplicate() {
+    let args = ["--deep-resolve", "soc.sv", "cfg_pkg.sv", "other_pkg.sv"];
+    let output = run(&fixture("deep_resolve"), &[&["--on-duplicate", "warn"], &args[..]].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "cfg_pkg.sv\nsoc.sv\nother_pkg.sv\n");
+    assert!(stderr(&output).contains("soc.sv uses NUM_CORES, which is declared in both cfg_pkg.sv and other_pkg.sv, using cfg_pkg.sv"), "{}", stderr(&output));
+    assert_eq!(sources("deep_resolve", &[&["--on-duplicate", "last"], &args[..]].concat()), ["other_pkg.sv", "soc.sv", "cfg_pkg.sv"]);
+}
+
+#[test]
+fn interface_passed_to_a_generic_port() {
+    let args = ["top.sv", "mon.sv", "axi_if.sv"];
+    let graph = order("generic_port", &[&["graph", "--edge-labels"], &args[..]].concat());
+    assert!(graph.contains("    \"top.sv\" -> \"axi_if.sv\" [label=\"interface axi_if\\ninterface on generic port mon.bus\"];\n"), "{}", graph);
+    let output = run(&fixture("generic_port"), &[&["--lint"], &args[..]].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Warning: mon.sv: port bus of module mon is a generic interface, its order only follows from instantiations\n"), "{}", stderr(&output));
+}
+
+#[test]
+fn timescale_file_first() {
+    assert_eq!(sources("timescale", &["a.sv", "b.sv", "c.sv"]), ["a.sv", "b.sv", "c.sv"]);
+    assert_eq!(sources("timescale", &["--timescale-first", "a.sv", "b.sv", "c.sv"]), ["c.sv", "a.sv", "b.sv"]);
+}
+
+#[test]
+fn files_without_timescale_linted_on_their_own() {
+    let output = run(&fixture("timescale"), &["--lint-timescale", "a.sv", "b.sv", "c.sv"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stderr(&output), "\
+Warning: a.sv: no `timescale directive, inherits the one from previously compiled files
+Warning: b.sv: no `timescale directive, inherits the one from previously compiled files
+");
+    let output = run(&fixture("timescale"), &["--lint", "a.sv", "b.sv", "c.sv"]);
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn preferred_implementation_only() {
+    let args = ["--prefer-file", "*_fpga.sv", "top.sv", "sram_model_rtl.sv", "sram_model_fpga.sv"];
+    assert_eq!(sources("prefer_file", &args), ["sram_model_fpga.sv", "top.sv"]);
+    assert_eq!(sources("prefer_file", &[&["--top", "top"], &args[..]].concat()), ["sram_model_fpga.sv", "top.sv"]);
+    // Without a preference both are compiled, the duplicate policy picks the one top uses
+    assert_eq!(sources("prefer_file", &["--on-duplicate", "first", "top.sv", "sram_model_rtl.sv", "sram_model_fpga.sv"]), ["sram_model_rtl.sv", "top.sv", "sram_model_fpga.sv"]);
+}
+
+#[test]
+fn program_before_its_instance() {
+    let output = run(&fixture("program"), &["-vv", "harness.sv", "tb_main.sv"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "tb_main.sv\nharness.sv\n");
+    assert!(stderr(&output).contains("harness.sv uses program tb_main from tb_main.sv\n"), "{}", stderr(&output));
+}
+
+#[test]
+fn checker_instantiated_in_a_generate_block() {
+    assert_eq!(sources("checker", &["arbiter.sv", "handshake_chk.sv"]), ["handshake_chk.sv", "arbiter.sv"]);
+}
+
+#[test]
+fn primitive_before_its_instance() {
+    assert_eq!(sources("udp", &["latch_wrap.v", "my_latch.v"]), ["my_latch.v", "latch_wrap.v"]);
+}
+
+#[test]
+fn gate_primitives_are_not_module_uses() {
+    let report: serde_json::Value = serde_json::from_str(&order("netlist", &["--format", "json", "netlist.v", "dff_cell.v"])).unwrap();
+    assert_eq!(report["order"], serde_json::json!(["dff_cell.v", "netlist.v"]));
+    // The gates leave the cell as the only module the netlist uses
+    assert_eq!(report["files"][1]["uses"]["modules"], serde_json::json!(["dff_cell"]));
+}
+
+#[test]
+fn bind_after_target_and_bound_module() {
+    assert_eq!(sources("bind", &["dut_binds.sv", "assert_checker.sv", "dut_core.sv"]), ["assert_checker.sv", "dut_core.sv", "dut_binds.sv"]);
+}
+
+#[test]
+fn config_after_the_modules_it_configures() {
+    let output = run(&fixture("config"), &["-v", "cfg.sv", "top.sv", "sram_fast.sv", "sram.sv"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "sram.sv\ntop.sv\nsram_fast.sv\ncfg.sv\n");
+    assert!(stderr(&output).contains("cfg.sv configures sram, sram_fast, top\n"), "{}", stderr(&output));
+}
+
+#[test]
+fn nested_module_is_not_a_global_definition() {
+    let output = run(&fixture("nested_module"), &["-vv", "top.sv", "outer.sv", "inner.sv"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "outer.sv\ninner.sv\ntop.sv\n");
+    assert!(stderr(&output).contains("top.sv uses module inner from inner.sv\n"), "{}", stderr(&output));
+    assert!(stderr(&output).contains("outer.sv declares nested module inner, not visible to other files\n"), "{}", stderr(&output));
+}
+
+#[test]
+fn forward_typedef_is_not_the_class() {
+    assert_eq!(sources("forward_typedef", &["txn_queue.sv", "my_txn.sv"]), ["my_txn.sv", "txn_queue.sv"]);
+}
+
+#[test]
+fn interface_class_before_implements() {
+    let output = run(&fixture("interface_class"), &["-vv", "key.sv", "item.sv", "sort_pkg.sv", "comparable.sv"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "sort_pkg.sv\nkey.sv\ncomparable.sv\nitem.sv\n");
+    // A scoped name in implements is an edge to the package
+    assert!(stderr(&output).contains("key.sv uses package/class sort_pkg from sort_pkg.sv\n"), "{}", stderr(&output));
+}
+
+#[test]
+fn parameterized_virtual_interface_before_the_class() {
+    assert_eq!(sources("virtual_interface", &["bus_driver.sv", "my_bus_if.sv"]), ["my_bus_if.sv", "bus_driver.sv"]);
+}
+
+#[test]
+fn interface_ports_with_and_without_modport() {
+    let report: serde_json::Value = serde_json::from_str(&order("modport_ports", &["--format", "json", "dut.sv", "axi_if.sv"])).unwrap();
+    assert_eq!(report["order"], serde_json::json!(["axi_if.sv", "dut.sv"]));
+    // The modport isn't a module of its own
+    let dut = report["files"].as_array().unwrap().iter().find(|file| file["path"] == "dut.sv").unwrap();
+    assert_eq!(dut["uses"]["interfaces"], serde_json::json!(["axi_if"]));
+    assert_eq!(dut["uses"]["modules"], serde_json::json!([]));
+}
+
+#[test]
+fn duplicate_module_by_policy() {
+    let args = ["top.sv", "fifo_a.sv", "fifo_b.sv"];
+    let policy = |policy: &str| run(&fixture("duplicate"), &[&["--on-duplicate", policy], &args[..]].concat());
+    let output = policy("error");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert_eq!(stderr(&output), "Error: fifo is defined in both fifo_a.sv and fifo_b.sv\n");
+    let output = policy("warn");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "fifo_a.sv\ntop.sv\nfifo_b.sv\n");
+    assert_eq!(stderr(&output), "Warning: fifo is defined in both fifo_a.sv and fifo_b.sv, using fifo_a.sv\n");
+    for (name, first) in [("first", "fifo_a.sv"), ("last", "fifo_b.sv")] {
+        let output = policy(name);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8_lossy(&output.stdout).lines().next(), Some(first), "{}", name);
+        assert_eq!(stderr(&output), "");
+    }
+}
+
+#[test]
+fn dpi_imports_are_not_packages() {
+    let report: serde_json::Value = serde_json::from_str(&order("dpi", &["--format", "json", "c_models.sv", "util_pkg.sv"])).unwrap();
+    assert_eq!(report["order"], serde_json::json!(["util_pkg.sv", "c_models.sv"]));
+    // Only the package import next to the DPI imports and exports is a use
+    let c_models = report["files"].as_array().unwrap().iter().find(|file| file["path"] == "c_models.sv").unwrap();
+    assert_eq!(c_models["uses"]["packages_and_classes"], serde_json::json!(["util_pkg"]));
+    assert_eq!(c_models["unresolved"], serde_json::json!([]));
+}
+
+#[test]
+fn escaped_identifiers_match_simple_ones() {
+    // plain_cell is declared escaped and used simple, fifo the other way around
+    let output = run(&fixture("escaped_ident"), &["-vv", "top.sv", "weird.sv", "plain_cell.sv", "fifo.sv"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "weird.sv\nplain_cell.sv\nfifo.sv\ntop.sv\n");
+    for edge in ["top.sv uses module fifo from fifo.sv\n", "top.sv uses module my-weird.name from weird.sv\n", "top.sv uses module plain_cell from plain_cell.sv\n"] {
+        assert!(stderr(&output).contains(edge), "{}", stderr(&output));
+    }
+}
+
+const EXPLAIN: &[&str] = &["soc.sv", "core.sv", "alu.sv", "word_pkg.sv"];
+
+#[test]
+fn explain_three_levels_deep() {
+    assert_eq!(order("explain", &[&["--explain", "word_pkg.sv"], EXPLAIN].concat()), "\
+word_pkg.sv
+  is number 1 of the 4 files in the order
+  depends on: nothing
+  is used by:
+    alu.sv for package/class word_pkg
+  is reached from the roots:
+    soc.sv
+      core.sv for module core
+        alu.sv for module alu
+          word_pkg.sv for package/class word_pkg
+");
+    // A name explains the file defining it, from the chosen top
+    let explanation = order("explain", &[&["--explain", "word_pkg", "--top", "core"], EXPLAIN].concat());
+    assert!(explanation.ends_with("\
+  is reached from the roots:
+    core.sv
+      alu.sv for module alu
+        word_pkg.sv for package/class word_pkg
+"), "{}", explanation);
+}
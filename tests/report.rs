@@ -0,0 +1,81 @@
+mod common;
+
+use common::order;
+use serde_json::Value;
+
+fn json_report(name: &str, args: &[&str]) -> Value {
+    serde_json::from_str(&order(name, &[&["--format", "json"], args].concat())).unwrap()
+}
+
+#[test]
+fn passthrough_files_tagged() {
+    let report = json_report("passthrough", &["top.sv", "glue.vhd", "leaf.sv"]);
+    let files = report["files"].as_array().unwrap();
+    let passthrough: Vec<_> = files.iter().map(|file| (file["path"].as_str().unwrap(), file["passthrough"].as_bool().unwrap())).collect();
+    assert_eq!(passthrough, [("glue.vhd", true), ("leaf.sv", false), ("top.sv", false)]);
+    assert!(files[0].get("defines").is_none());
+    assert_eq!(report["order"], serde_json::json!(["leaf.sv", "glue.vhd", "top.sv"]));
+}
+
+#[test]
+fn missing_sources_listed_as_skipped() {
+    let report = json_report("passthrough", &["--allow-missing-sources", "top.sv", "gone.sv", "leaf.sv"]);
+    assert_eq!(report["skipped"], serde_json::json!([{"path": "gone.sv", "reason": "missing"}]));
+    assert_eq!(report["order"], serde_json::json!(["leaf.sv", "top.sv"]));
+}
+
+const REPORT: &[&str] = &["top.sv", "leaf.sv", "bus_pkg.sv"];
+
+/// Compares with the file of the fixture, that the output can replace after checking the difference
+fn assert_golden(name: &str, golden: &str, output: &str) {
+    let expected = std::fs::read_to_string(common::fixture(name).join(golden)).unwrap();
+    assert!(output == expected, "the output differs from {}/{}:\n{}", name, golden, output);
+}
+
+#[test]
+fn json_report_golden() {
+    assert_golden("report", "expected.json", &order("report", &[&["--format", "json"], REPORT].concat()));
+}
+
+#[test]
+fn yaml_report_golden() {
+    assert_golden("report", "expected.yaml", &order("report", &[&["--format", "yaml"], REPORT].concat()));
+}
+
+#[test]
+fn yaml_report_same_as_json() {
+    let yaml = common::yaml::parse(&order("report", &[&["--format", "yaml"], REPORT].concat()));
+    assert_eq!(yaml, json_report("report", REPORT));
+    let yaml = common::yaml::parse(&order("passthrough", &["--format", "yaml", "--report-unused", "top.sv", "glue.vhd", "leaf.sv"]));
+    assert_eq!(yaml, json_report("passthrough", &["--report-unused", "top.sv", "glue.vhd", "leaf.sv"]));
+}
+
+#[test]
+fn unresolved_report_golden() {
+    let dir = common::temp_dir("unresolved-report");
+    let path = dir.join("unresolved.json");
+    let output = common::run(&common::fixture("unresolved_report"), &["--ignore-module", "sky130_*", "--unresolved-report", path.to_str().unwrap(), "top.sv", "sub.sv"]);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    // The order is still printed in the same run
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "sub.sv\ntop.sv\n");
+    assert_golden("unresolved_report", "expected.json", &std::fs::read_to_string(&path).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn orphan_ip_of_a_filelist_reported_unused() {
+    let args = ["--top", "top", "--report-unused", "-f", "soc.f"];
+    let output = common::run(&common::fixture("orphan_ip"), &args);
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "core.sv\ntop.sv\n");
+    assert_eq!(common::stderr(&output), "\
+3 files are not needed by any root:
+    ip/dma/dma.sv: module dma
+    ip/dma/dma_legacy.sv: defines nothing
+    ip/dma/dma_pkg.sv: package/class dma_pkg
+");
+    let unused = &json_report("orphan_ip", &args)["unused"];
+    let split: Vec<_> = unused.as_array().unwrap().iter().map(|file| (file["path"].as_str().unwrap(), file["defines_nothing"].as_bool().unwrap())).collect();
+    assert_eq!(split, [("ip/dma/dma.sv", false), ("ip/dma/dma_legacy.sv", true), ("ip/dma/dma_pkg.sv", false)]);
+    assert_eq!(unused[0]["defines"]["modules"], serde_json::json!(["dma"]));
+}